@@ -1,4 +1,4 @@
-mod lfu_cache;
+pub mod lfu_cache;
 mod linked_list;
 mod list_node;
 
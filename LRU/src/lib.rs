@@ -1,6 +1,6 @@
 mod linked_list;
 mod list_node;
-mod lru_cache;
+pub mod lru_cache;
 
 
 #[cfg(test)]
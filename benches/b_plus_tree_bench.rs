@@ -0,0 +1,173 @@
+use std::collections::BTreeMap;
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use b_plus_tree::index::b_plus_tree::BPlusTree;
+use LFU::lfu_cache::LFUCache;
+use LRU::lru_cache::LRUCache;
+
+const ENTRY_COUNT: i32 = 10_000;
+const RANGE_LEN: i32 = 100;
+const CACHE_CAPACITY: i32 = 1_000;
+
+/// (internal_max_size, leaf_max_size)，除了两者相等的组合，还有内部节点小叶子节点大以及相反的组合，
+/// 用于分别调整两个参数
+const NODE_SIZES: [(usize, usize); 12] = [
+    (3, 3), (4, 4), (8, 8), (16, 16), (64, 64), (128, 128),
+    (4, 64), (16, 128), (8, 256),
+    (64, 4), (128, 16), (256, 8)
+];
+
+fn sequential_keys() -> Vec<i32> {
+    (0..ENTRY_COUNT).collect()
+}
+
+/// 7919和ENTRY_COUNT互质，所以这是0..ENTRY_COUNT的一个确定的乱序排列
+fn random_keys() -> Vec<i32> {
+    (0..ENTRY_COUNT).map(|i| (i * 7919) % ENTRY_COUNT).collect()
+}
+
+fn node_size_id(internal_max_size: usize, leaf_max_size: usize) -> String {
+    format!("{}x{}", internal_max_size, leaf_max_size)
+}
+
+fn build_tree(internal_max_size: usize, leaf_max_size: usize, keys: &[i32]) -> BPlusTree {
+    let mut tree = BPlusTree::new(String::from("bench"), internal_max_size, leaf_max_size);
+    for &key in keys {
+        tree.insert(key, key);
+    }
+    tree
+}
+
+fn build_map(keys: &[i32]) -> BTreeMap<i32, i32> {
+    keys.iter().map(|&key| (key, key)).collect()
+}
+
+fn bench_insert(c: &mut Criterion) {
+    for (name, keys) in [("insert_sequential", sequential_keys()), ("insert_random", random_keys())] {
+        let mut group = c.benchmark_group(name);
+        for (internal_max_size, leaf_max_size) in NODE_SIZES {
+            group.bench_with_input(BenchmarkId::new("BPlusTree", node_size_id(internal_max_size, leaf_max_size)), &keys, |b, keys| {
+                b.iter(|| build_tree(internal_max_size, leaf_max_size, black_box(keys)));
+            });
+        }
+        group.bench_with_input(BenchmarkId::new("BTreeMap", "-"), &keys, |b, keys| {
+            b.iter(|| build_map(black_box(keys)));
+        });
+        group.finish();
+    }
+}
+
+fn bench_get(c: &mut Criterion) {
+    let keys = random_keys();
+    let mut group = c.benchmark_group("get");
+    for (internal_max_size, leaf_max_size) in NODE_SIZES {
+        let tree = build_tree(internal_max_size, leaf_max_size, &keys);
+        group.bench_function(BenchmarkId::new("BPlusTree", node_size_id(internal_max_size, leaf_max_size)), |b| {
+            b.iter(|| {
+                for &key in &keys {
+                    black_box(tree.get_value(key));
+                }
+            });
+        });
+    }
+    let map = build_map(&keys);
+    group.bench_function(BenchmarkId::new("BTreeMap", "-"), |b| {
+        b.iter(|| {
+            for key in &keys {
+                black_box(map.get(key));
+            }
+        });
+    });
+    group.finish();
+}
+
+fn bench_iter(c: &mut Criterion) {
+    let keys = random_keys();
+    let mut group = c.benchmark_group("iter");
+    for (internal_max_size, leaf_max_size) in NODE_SIZES {
+        let tree = build_tree(internal_max_size, leaf_max_size, &keys);
+        group.bench_function(BenchmarkId::new("BPlusTree", node_size_id(internal_max_size, leaf_max_size)), |b| {
+            b.iter(|| tree.iter().fold(0i64, |sum, value| sum + value as i64));
+        });
+    }
+    let map = build_map(&keys);
+    group.bench_function(BenchmarkId::new("BTreeMap", "-"), |b| {
+        b.iter(|| map.values().fold(0i64, |sum, value| sum + *value as i64));
+    });
+    group.finish();
+}
+
+fn bench_range(c: &mut Criterion) {
+    let keys = random_keys();
+    let starts: Vec<i32> = (0..ENTRY_COUNT - RANGE_LEN).step_by(97).collect();
+    let mut group = c.benchmark_group("range");
+    for (internal_max_size, leaf_max_size) in NODE_SIZES {
+        let tree = build_tree(internal_max_size, leaf_max_size, &keys);
+        group.bench_function(BenchmarkId::new("BPlusTree", node_size_id(internal_max_size, leaf_max_size)), |b| {
+            b.iter(|| {
+                for &start in &starts {
+                    black_box(tree.range(start, start + RANGE_LEN).count());
+                }
+            });
+        });
+    }
+    let map = build_map(&keys);
+    group.bench_function(BenchmarkId::new("BTreeMap", "-"), |b| {
+        b.iter(|| {
+            for &start in &starts {
+                black_box(map.range(start..start + RANGE_LEN).count());
+            }
+        });
+    });
+    group.finish();
+}
+
+fn bench_remove(c: &mut Criterion) {
+    let keys = random_keys();
+    let mut group = c.benchmark_group("remove");
+    for (internal_max_size, leaf_max_size) in NODE_SIZES {
+        group.bench_function(BenchmarkId::new("BPlusTree", node_size_id(internal_max_size, leaf_max_size)), |b| {
+            b.iter_batched(|| build_tree(internal_max_size, leaf_max_size, &keys), |mut tree| {
+                for &key in keys.iter().rev() {
+                    tree.remove(key);
+                }
+                tree
+            }, BatchSize::LargeInput);
+        });
+    }
+    group.bench_function(BenchmarkId::new("BTreeMap", "-"), |b| {
+        b.iter_batched(|| build_map(&keys), |mut map| {
+            for key in keys.iter().rev() {
+                map.remove(key);
+            }
+            map
+        }, BatchSize::LargeInput);
+    });
+    group.finish();
+}
+
+fn bench_cache(c: &mut Criterion) {
+    let keys = random_keys();
+    let mut group = c.benchmark_group("cache");
+    group.bench_function("LRUCache", |b| {
+        b.iter(|| {
+            let mut cache = LRUCache::new(CACHE_CAPACITY);
+            for &key in &keys {
+                cache.put(key % (CACHE_CAPACITY * 2), key);
+                black_box(cache.get(key % CACHE_CAPACITY));
+            }
+        });
+    });
+    group.bench_function("LFUCache", |b| {
+        b.iter(|| {
+            let mut cache = LFUCache::new(CACHE_CAPACITY);
+            for &key in &keys {
+                cache.put(key % (CACHE_CAPACITY * 2), key);
+                black_box(cache.get(key % CACHE_CAPACITY));
+            }
+        });
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_insert, bench_get, bench_iter, bench_range, bench_remove, bench_cache);
+criterion_main!(benches);
@@ -0,0 +1,60 @@
+#![no_main]
+
+//! 把任意字节解码成一串操作，同时作用在BPlusTree和BTreeMap上并比较结果。
+//!
+//! 前两个字节决定internal_max_size和leaf_max_size，取值都很小(3..=6)，
+//! 这样几乎每次插入和删除都会触发split、coalesce和redistribute。
+//! 之后每三个字节是一个操作：操作码 + 两个字节的key/参数。
+
+use std::collections::BTreeMap;
+use libfuzzer_sys::fuzz_target;
+use b_plus_tree::index::b_plus_tree::BPlusTree;
+
+const MIN_MAX_SIZE: usize = 3;
+const MAX_SIZE_SPAN: u8 = 4;
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 2 {
+        return;
+    }
+
+    let internal_max_size = MIN_MAX_SIZE + (data[0] % MAX_SIZE_SPAN) as usize;
+    let leaf_max_size = MIN_MAX_SIZE + (data[1] % MAX_SIZE_SPAN) as usize;
+    let mut tree = BPlusTree::new(String::from("fuzz"), internal_max_size, leaf_max_size);
+    let mut model = BTreeMap::new();
+
+    for (step, op) in data[2..].chunks_exact(3).enumerate() {
+        // key只取一个字节，让插入和删除经常命中已经存在的key
+        let key = op[1] as i32;
+        let arg = op[2] as i32;
+
+        match op[0] % 5 {
+            0 | 1 => {
+                let inserted = tree.insert(key, step as i32);
+                assert_eq!(!model.contains_key(&key), inserted);
+                model.entry(key).or_insert(step as i32);
+            }
+            2 => {
+                tree.remove(key);
+                model.remove(&key);
+            }
+            3 => {
+                assert_eq!(model.get(&key).copied(), tree.get_value(key));
+            }
+            _ => {
+                let end_key = key + arg;
+                let actual: Vec<i32> = tree.range(key, end_key).collect();
+                let expected: Vec<i32> = model.range(key..end_key).map(|(_, v)| *v).collect();
+                assert_eq!(expected, actual);
+            }
+        }
+
+        if let Err(corruption) = tree.validate() {
+            panic!("step {}: {}", step, corruption);
+        }
+    }
+
+    let actual: Vec<i32> = tree.iter().collect();
+    let expected: Vec<i32> = model.values().copied().collect();
+    assert_eq!(expected, actual);
+});
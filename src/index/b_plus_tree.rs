@@ -1,10 +1,24 @@
-use std::fmt::{Debug, Formatter};
-use std::fs::File;
+use std::cell::{Ref, RefCell};
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter};
+use std::io;
 use std::io::Write;
 use std::mem;
-use crate::iterator::b_plus_tree_iterator::BPlusTreeIter;
+use std::rc::Rc;
+use crate::index::b_plus_tree_aggregate::Monoid;
+use crate::index::b_plus_tree_changes::ChangeLog;
+use crate::index::b_plus_tree_merge::MergeOperator;
+use crate::index::b_plus_tree_recorder::{Frame, FrameFormat, FrameRecorder, StructuralEvent};
+use crate::index::b_plus_tree_stats::{StatsVisitor, TreeStats};
+use crate::index::b_plus_tree_trace::{TraceOp, TraceWriter};
+use crate::index::b_plus_tree_visitor::WalkOrder;
+use crate::iterator::b_plus_tree_iterator::{BPlusTreeEntries, BPlusTreeEntriesMut, BPlusTreeIter};
 use crate::page::b_plus_tree_page::{BPlusTreePage, Page, RcPage, SizeT, ValueType};
-use crate::page::b_plus_tree_page::BPlusTreePageType::{InternalPage, InvalidIndexPage, LeafPage};
+use crate::page::b_plus_tree_page::BPlusTreePageType::{InternalPage, LeafPage};
+
+/// 从根节点向下查找叶子节点时经过的内部节点，以及每个内部节点中选中的孩子下标
+pub(crate) type SearchPath = Vec<(RcPage, usize)>;
 
 pub enum Operation {
     FIND,
@@ -13,86 +27,435 @@ pub enum Operation {
     DELETE
 }
 
+/// validate()发现的结构性错误，page_id指出出错的节点
+#[derive(Clone, Debug, PartialEq)]
+pub enum TreeCorruption {
+    /// 节点类型既不是内部节点也不是叶子节点，或者内部节点中存放的不是子节点指针
+    InvalidPage { page_id: usize },
+    /// 节点内的关键字没有严格递增
+    UnsortedKeys { page_id: usize, index: usize },
+    /// 关键字超出了父节点分隔关键字给出的范围 [lower, upper)
+    KeyOutOfRange { page_id: usize, key: i32, lower: Option<i32>, upper: Option<i32> },
+    /// 节点元素个数小于get_min_size()
+    Underflow { page_id: usize, size: SizeT, min_size: SizeT },
+    /// 节点元素个数超过了该节点允许的最大容量
+    Overflow { page_id: usize, size: SizeT, max_size: SizeT },
+    /// 叶子节点不在同一层
+    UnevenLeafDepth { page_id: usize, depth: usize, expected: usize },
+    /// 子节点的parent_page_没有指向真正的父节点
+    BadParentPointer { page_id: usize },
+    /// 叶子节点的next_page_没有指向下一个叶子节点
+    BrokenLeafChain { page_id: usize },
+    /// 节点中下标为index的元素记录的子树key个数与实际不符
+    BadSubtreeCount { page_id: usize, index: usize, count: SizeT, expected: SizeT },
+    /// 节点中下标为index的元素记录的子树聚合值与实际不符
+    BadSubtreeAggregate { page_id: usize, index: usize, aggregate: i64, expected: i64 }
+}
+
+impl Display for TreeCorruption {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TreeCorruption::InvalidPage { page_id } => {
+                write!(f, "page {} has an invalid type or entry", page_id)
+            }
+            TreeCorruption::UnsortedKeys { page_id, index } => {
+                write!(f, "page {} keys are not sorted at index {}", page_id, index)
+            }
+            TreeCorruption::KeyOutOfRange { page_id, key, lower, upper } => {
+                write!(f, "page {} key {} is outside of [{:?}, {:?})", page_id, key, lower, upper)
+            }
+            TreeCorruption::Underflow { page_id, size, min_size } => {
+                write!(f, "page {} has {} entries, min_size is {}", page_id, size, min_size)
+            }
+            TreeCorruption::Overflow { page_id, size, max_size } => {
+                write!(f, "page {} has {} entries, at most {} are allowed", page_id, size, max_size)
+            }
+            TreeCorruption::UnevenLeafDepth { page_id, depth, expected } => {
+                write!(f, "leaf page {} is at depth {}, expected {}", page_id, depth, expected)
+            }
+            TreeCorruption::BadParentPointer { page_id } => {
+                write!(f, "page {} has a wrong parent pointer", page_id)
+            }
+            TreeCorruption::BrokenLeafChain { page_id } => {
+                write!(f, "leaf page {} has a wrong next pointer", page_id)
+            }
+            TreeCorruption::BadSubtreeCount { page_id, index, count, expected } => {
+                write!(f, "page {} entry {} counts {} keys, expected {}", page_id, index, count, expected)
+            }
+            TreeCorruption::BadSubtreeAggregate { page_id, index, aggregate, expected } => {
+                write!(f, "page {} entry {} caches aggregate {}, expected {}", page_id, index, aggregate, expected)
+            }
+        }
+    }
+}
+
+impl Error for TreeCorruption {}
+
+/// try_*系列方法返回的错误
+#[derive(Clone, Debug, PartialEq)]
+pub enum BPlusTreeError {
+    /// internal_max_size小于3
+    InvalidInternalMaxSize(SizeT),
+    /// leaf_max_size小于3
+    InvalidLeafMaxSize(SizeT),
+    /// 操作过程中发现树的结构已经损坏，比如子节点指针为空、父节点中找不到子节点
+    Corrupted(TreeCorruption),
+    /// 调用merge_value()之前没有设置合并函数
+    NoMergeOperator,
+    /// 变更日志没有打开
+    ChangeLogDisabled,
+    /// 序号为sequence的变更事件已经不在变更日志中，日志中的事件序号范围是[oldest, next)
+    SequenceUnavailable { sequence: u64, oldest: u64, next: u64 }
+}
+
+impl Display for BPlusTreeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BPlusTreeError::InvalidInternalMaxSize(size) => {
+                write!(f, "internal_max_size must be at least 3, got {}", size)
+            }
+            BPlusTreeError::InvalidLeafMaxSize(size) => {
+                write!(f, "leaf_max_size must be at least 3, got {}", size)
+            }
+            BPlusTreeError::Corrupted(corruption) => {
+                write!(f, "tree is corrupted: {}", corruption)
+            }
+            BPlusTreeError::NoMergeOperator => {
+                write!(f, "no merge operator is set")
+            }
+            BPlusTreeError::ChangeLogDisabled => {
+                write!(f, "change log is not enabled")
+            }
+            BPlusTreeError::SequenceUnavailable { sequence, oldest, next } => {
+                write!(f, "change {} is not in the change log, which holds [{}, {})", sequence, oldest, next)
+            }
+        }
+    }
+}
+
+impl Error for BPlusTreeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            BPlusTreeError::Corrupted(corruption) => Some(corruption),
+            _ => None
+        }
+    }
+}
+
+impl From<TreeCorruption> for BPlusTreeError {
+    fn from(corruption: TreeCorruption) -> Self {
+        BPlusTreeError::Corrupted(corruption)
+    }
+}
+
 pub struct BPlusTree {
     index_name_: String,
     internal_max_size_: SizeT,
     leaf_max_size_: SizeT,
-    root_page_: Page
+    root_page_: Page,
+    len_: SizeT,
+    aggregator_: Option<Rc<dyn Monoid>>,
+    // entries_mut()访问过的叶子节点，其中的value可能被修改，这些叶子节点到根节点路径上缓存的聚合值
+    // 需要在下次修改树之前重新计算
+    dirty_leaves_: Vec<RcPage>,
+    merge_operator_: Option<Rc<dyn MergeOperator>>,
+    check_invariants_: bool,
+    split_count_: SizeT,
+    merge_count_: SizeT,
+    redistribute_count_: SizeT,
+    recorder_: Option<FrameRecorder>,
+    // get_value()只有&self，所以放在RefCell里
+    tracer_: RefCell<Option<TraceWriter>>,
+    // 与tracer_一样由trace()写入
+    change_log_: RefCell<Option<ChangeLog>>,
+    // 下一次打开的变更日志的第一个序号，保证同一棵树中的序号不会重复
+    next_change_sequence_: u64,
+    // 记录时调用entries_mut()之前的所有键值对，之后第一次记录操作或者修改树之前与当前的value比较，
+    // 为修改过的value补上update记录
+    value_snapshot_: RefCell<Option<Vec<(i32, i32)>>>
 }
 
 impl Debug for BPlusTree {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        todo!()
+        f.debug_struct("BPlusTree")
+            .field("index_name", &self.index_name_)
+            .field("internal_max_size", &self.internal_max_size_)
+            .field("leaf_max_size", &self.leaf_max_size_)
+            .field("root_page_id", &self.root_page_.as_ref().map(|root_page| root_page.borrow().get_page_id()))
+            .finish_non_exhaustive()
     }
 }
 
 
 // public methods
 impl BPlusTree {
+    /// 与try_new()相同，参数不合法时panic
     pub fn new(index_name: String, internal_max_size: SizeT, leaf_max_size: SizeT) -> Self {
-        Self {
+        Self::try_new(index_name, internal_max_size, leaf_max_size).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// internal_max_size和leaf_max_size都至少为3，否则节点分裂后会出现只有一个孩子的内部节点或者空的叶子节点
+    pub fn try_new(index_name: String, internal_max_size: SizeT, leaf_max_size: SizeT) -> Result<Self, BPlusTreeError> {
+        if internal_max_size < 3 {
+            return Err(BPlusTreeError::InvalidInternalMaxSize(internal_max_size));
+        }
+        if leaf_max_size < 3 {
+            return Err(BPlusTreeError::InvalidLeafMaxSize(leaf_max_size));
+        }
+
+        Ok(Self {
             index_name_: index_name,
             internal_max_size_: internal_max_size,
             leaf_max_size_: leaf_max_size,
-            root_page_: None
+            root_page_: None,
+            len_: 0,
+            aggregator_: None,
+            dirty_leaves_: Vec::new(),
+            merge_operator_: None,
+            check_invariants_: false,
+            split_count_: 0,
+            merge_count_: 0,
+            redistribute_count_: 0,
+            recorder_: None,
+            tracer_: RefCell::new(None),
+            change_log_: RefCell::new(None),
+            next_change_sequence_: 0,
+            value_snapshot_: RefCell::new(None)
+        })
+    }
+
+    /// 打开后，debug构建下每次insert/remove之后都会调用validate()，
+    /// 发现错误时try_insert()/try_remove()返回BPlusTreeError::Corrupted，insert()/remove()直接panic
+    ///
+    /// release构建下该选项不起作用
+    pub fn set_check_invariants(&mut self, check_invariants: bool) {
+        self.check_invariants_ = check_invariants;
+    }
+
+    /// 开始录制：之后每次leaf split、insert_into_parent、coalesce、redistribute和adjust_root
+    /// 都会按format渲染一帧整棵树，发生变化的节点会被高亮
+    ///
+    /// split_off()和append()拼接或拆分整棵树时修复边界节点用到的这些操作不会录制
+    pub fn start_recording(&mut self, format: FrameFormat) {
+        self.recorder_ = Some(FrameRecorder::new(format));
+    }
+
+    /// 停止录制并返回录制到的所有帧，没有在录制时返回空
+    pub fn stop_recording(&mut self) -> Vec<Frame> {
+        self.recorder_.take().map(|recorder| recorder.into_frames()).unwrap_or_default()
+    }
+
+    /// 开始把之后的每次insert/remove/get_value连同参数和结果写入writer，格式见b_plus_tree_trace，
+    /// 可以用replay_trace()在一棵新树上重放。writer最好带缓冲，比如BufWriter<File>
+    ///
+    /// 树不为空时，先按key从小到大为当前的每个键值对写一条insert记录，回放时从相同的内容开始
+    ///
+    /// 已经在记录时，先结束之前的记录
+    pub fn start_trace(&mut self, writer: impl Write + 'static) -> io::Result<()> {
+        self.stop_trace()?;
+        let mut tracer = TraceWriter::new(Box::new(writer), self.internal_max_size_, self.leaf_max_size_)?;
+        for (key, value) in self.entries() {
+            tracer.record(TraceOp::Insert { key: *key, value: *value, inserted: true });
+        }
+        *self.tracer_.borrow_mut() = Some(tracer);
+        Ok(())
+    }
+
+    /// 结束记录并flush，返回记录过程中遇到的第一个写入错误
+    pub fn stop_trace(&mut self) -> io::Result<()> {
+        self.flush_value_updates();
+        match self.tracer_.take() {
+            Some(tracer) => tracer.finish(),
+            None => Ok(())
         }
     }
 
+    pub fn get_internal_max_size(&self) -> SizeT {
+        self.internal_max_size_
+    }
+
+    pub fn get_leaf_max_size(&self) -> SizeT {
+        self.leaf_max_size_
+    }
+
+    pub fn get_index_name(&self) -> &str {
+        &self.index_name_
+    }
+
+    /// entries()、get()等方法返回的引用依赖于节点只能通过&mut BPlusTree修改，所以节点不对外公开，
+    /// 外部的检查工具使用walk()和PageView
+    pub(crate) fn get_root_page(&self) -> Page {
+        self.root_page_.clone()
+    }
+
     pub fn iter(&self) -> BPlusTreeIter {
-        let left_most_leaf_page = self.find_leaf_page(0, Operation::FIND, true, false);
+        let left_most_leaf_page = self.expect_ok(self.find_leaf_page(0, Operation::FIND, true, false));
         if left_most_leaf_page.is_none() {
-            return BPlusTreeIter::new(None, 0);
+            return BPlusTreeIter::new(None, 0, None);
+        }
+        BPlusTreeIter::new(left_most_leaf_page, 0, None)
+    }
+
+    /// 按key从小到大遍历 [start_key, end_key) 范围内的value
+    pub fn range(&self, start_key: i32, end_key: i32) -> BPlusTreeIter {
+        let leaf_page = self.expect_ok(self.find_leaf_page(start_key, Operation::FIND, false, false));
+        if leaf_page.is_none() {
+            return BPlusTreeIter::new(None, 0, None);
         }
-        BPlusTreeIter::new(left_most_leaf_page, 0)
+        let start_index = leaf_page.as_ref().unwrap().borrow().key_index(start_key);
+        BPlusTreeIter::new(leaf_page, start_index, Some(end_key))
+    }
+
+    /// 按key从小到大遍历(&key, &value)
+    pub fn entries(&self) -> BPlusTreeEntries<'_> {
+        let left_most_leaf_page = self.expect_ok(self.find_leaf_page(0, Operation::FIND, true, false));
+        // SAFETY: 节点只能通过&mut self修改，迭代器借用了self
+        unsafe { BPlusTreeEntries::new(left_most_leaf_page) }
+    }
+
+    /// 按key从小到大遍历(&key, &mut value)，只能修改value，不能修改key
+    ///
+    /// 记录操作日志或者变更日志时，修改过的value在下一次记录操作或者修改树之前补记为update
+    pub fn entries_mut(&mut self) -> BPlusTreeEntriesMut<'_> {
+        // 上一次entries_mut()留下的过期聚合值先修复，dirty_leaves_中只保留这一次访问的叶子节点
+        let result = self.rebuild_dirty_aggregates();
+        self.expect_ok(result);
+        if self.is_tracing() {
+            *self.value_snapshot_.borrow_mut() = Some(self.entries().map(|(key, value)| (*key, *value)).collect());
+        }
+        let left_most_leaf_page = self.expect_ok(self.find_leaf_page(0, Operation::FIND, true, false));
+        let visited_pages = if self.aggregator_.is_some() { Some(&mut self.dirty_leaves_) } else { None };
+        // SAFETY: 迭代器独占借用了self，期间节点不会被其他人访问
+        unsafe { BPlusTreeEntriesMut::new(left_most_leaf_page, visited_pages) }
     }
 
     pub fn is_empty(&self) -> bool {
         self.root_page_.is_none()
     }
 
-    pub fn insert(&mut self, key: i32, value: i32) -> bool {
-        if self.is_empty() {
-            self.create_new_tree(key, value);
-            return true;
+    /// 树中key的个数
+    pub fn len(&self) -> SizeT {
+        self.len_
+    }
+
+    /// 删除所有的key，split/merge/redistribute的统计次数保持不变
+    pub fn clear(&mut self) {
+        if self.is_tracing() {
+            let keys: Vec<i32> = self.entries().map(|(key, _)| *key).collect();
+            for key in keys {
+                self.trace(TraceOp::Remove { key, removed: true });
+            }
         }
+        self.root_page_ = None;
+        self.len_ = 0;
+        self.dirty_leaves_.clear();
+    }
 
-        return self.insert_into_leaf(key, value);
+    /// 与try_insert()相同，树的结构损坏时panic
+    pub fn insert(&mut self, key: i32, value: i32) -> bool {
+        let result = self.try_insert(key, value);
+        self.expect_ok(result)
     }
 
+    /// 插入key，key已经存在时不覆盖原来的value并返回false
+    pub fn try_insert(&mut self, key: i32, value: i32) -> Result<bool, BPlusTreeError> {
+        self.traced(TraceOp::Insert { key, value, inserted: false }, |tree| tree.insert_entry(key, value),
+                    |inserted| TraceOp::Insert { key, value, inserted: *inserted })
+    }
+
+    /// 与try_get_value()相同，树的结构损坏时panic
     pub fn get_value(&self, key: i32) -> Option<i32> {
-        let leaf_page = self.find_leaf_page(key, Operation::FIND, false, false);
-        if leaf_page.is_none() {
-            return None;
-        }
-        let value = leaf_page.unwrap().borrow().lookup(key);
-        match value {
-            ValueType::Page(_) => { None }
-            ValueType::Value(v) => { v }
-        }
+        self.expect_ok(self.try_get_value(key))
+    }
+
+    pub fn try_get_value(&self, key: i32) -> Result<Option<i32>, BPlusTreeError> {
+        self.trace_begin(TraceOp::Get { key, value: None });
+        let result = self.lookup_value(key);
+        self.trace_end(result.as_ref().ok().map(|value| TraceOp::Get { key, value: *value }));
+        result
+    }
+
+    /// 与get_value()相同，但是返回value的引用
+    pub fn get(&self, key: &i32) -> Option<&i32> {
+        self.trace_begin(TraceOp::Get { key: *key, value: None });
+        let result = self.lookup_value_ref(*key);
+        self.trace_end(result.as_ref().ok().map(|value| TraceOp::Get { key: *key, value: value.copied() }));
+        self.expect_ok(result)
     }
 
+    pub fn contains_key(&self, key: &i32) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// 与try_remove()相同，树的结构损坏时panic
     pub fn remove(&mut self, key: i32) {
-        if self.is_empty() {
-            return;
-        }
+        let result = self.try_remove(key);
+        self.expect_ok(result);
+    }
 
-        let leaf_page = self.find_leaf_page(key, Operation::DELETE, false, false).unwrap();
-        let old_size = leaf_page.borrow().get_size();
-        let new_size = leaf_page.borrow_mut().remove_and_delete_record(key);
+    /// 删除key，返回key是否存在
+    pub fn try_remove(&mut self, key: i32) -> Result<bool, BPlusTreeError> {
+        self.traced(TraceOp::Remove { key, removed: false }, |tree| tree.remove_entry(key),
+                    |removed| TraceOp::Remove { key, removed: *removed })
+    }
 
-        if old_size == new_size {
-            return;
+    /// 统计树的形状：高度、每一层的节点个数和填充率，以及创建以来split、merge、redistribute的次数
+    ///
+    /// split_off()和append()拼接或拆分整棵树时修复边界节点用到的split、merge、redistribute不计入次数
+    pub fn stats(&self) -> TreeStats {
+        let stats = TreeStats {
+            split_count: self.split_count_,
+            merge_count: self.merge_count_,
+            redistribute_count: self.redistribute_count_,
+            ..Default::default()
+        };
+
+        let mut visitor = StatsVisitor::default();
+        self.walk(WalkOrder::BreadthFirst, &mut visitor);
+        visitor.finish(stats)
+    }
+
+    /// 检查整棵树的结构是否正确：
+    ///
+    /// (1) 每个节点内的关键字严格递增
+    ///
+    /// (2) 子树中的关键字都落在父节点分隔关键字给出的范围 [key(i), key(i+1)) 之内
+    ///
+    /// (3) 每个节点的元素个数在 [get_min_size(), 最大容量] 之内
+    ///
+    /// (4) 所有叶子节点在同一层
+    ///
+    /// (5) parent_page_指向真正的父节点，叶子节点的next_page_按顺序串联起所有叶子节点
+    ///
+    /// (6) 内部节点中每个孩子的count等于该子树中key的个数，叶子节点中的count都为1
+    pub fn validate(&self) -> Result<(), TreeCorruption> {
+        let root_page = match &self.root_page_ {
+            None => {
+                return Ok(());
+            }
+            Some(root_page) => {
+                root_page.clone()
+            }
+        };
+
+        if root_page.borrow().get_parent_page().is_some() {
+            return Err(TreeCorruption::BadParentPointer { page_id: root_page.borrow().get_page_id() });
         }
 
-        self.coalesce_or_redistribute(leaf_page);
+        let mut leaf_depth = None;
+        let mut leaf_pages = Vec::new();
+        let (count, _) = self.validate_page(root_page.clone(), None, None, 0, &mut leaf_depth, &mut leaf_pages)?;
+        if count != self.len_ {
+            return Err(TreeCorruption::BadSubtreeCount { page_id: root_page.borrow().get_page_id(), index: 0, count: self.len_, expected: count });
+        }
+        self.validate_leaf_chain(&leaf_pages)
     }
 
     pub fn print(&self) {
         if self.root_page_.is_none() {
             println!("Tree is Empty!");
         } else {
-            let root_page = self.root_page_.as_ref().unwrap();
-            println!("{}", self.to_string(root_page.clone()));
+            println!("{}", self.to_text());
         }
     }
 
@@ -100,202 +463,601 @@ impl BPlusTree {
         if self.root_page_.is_none() {
             println!("Tree is Empty!");
         } else {
-            let root_page = self.root_page_.as_ref().unwrap();
-            let graph_str = format!("digraph G {{{}}}", self.to_graph(root_page.clone()));
-            println!("{}", graph_str);
-
-            let path = "tree.dot";
-            let mut f = File::create(path).unwrap();
-            f.write_all(graph_str.as_bytes()).expect("write file failed");
+            println!("{}", self.to_dot());
         }
     }
 }
 
 // private methods
 impl BPlusTree {
-    fn to_graph(&self, cur_page: RcPage) -> String {
-        let leaf_prefix = String::from("LEAF_");
-        let internal_prefix = String::from("INT_");
-        let mut graph_str = String::new();
+    fn insert_entry(&mut self, key: i32, value: i32) -> Result<bool, BPlusTreeError> {
+        self.rebuild_dirty_aggregates()?;
+        let inserted = if self.is_empty() {
+            self.create_new_tree(key, value);
+            true
+        } else {
+            self.insert_into_leaf(key, value)?
+        };
 
-        if cur_page.borrow().is_leaf_page() {
-            graph_str.push_str(format!("{}{}", leaf_prefix, cur_page.borrow().get_page_id()).as_str());
-            // print node properties
-            graph_str.push_str("[shape=plain color=green ");
-            // print data of the node
-            graph_str.push_str("label=<<TABLE BORDER=\"0\" CELLBORDER=\"1\" CELLSPACING=\"0\" CELLPADDING=\"4\">\n");
-            // print data
-            graph_str.push_str(format!("<TR><TD COLSPAN=\"{}\">P={}</TD></TR>\n", cur_page.borrow().get_size(), cur_page.borrow().get_page_id()).as_str());
-            graph_str.push_str(format!("<TR><TD COLSPAN=\"{}\">max_size={},min_size={}</TD></TR>\n<TR>", cur_page.borrow().get_size(), cur_page.borrow().get_max_size(), cur_page.borrow().get_min_size()).as_str());
-            for i in 0..cur_page.borrow().get_size() {
-                graph_str.push_str(format!("<TD>{}</TD>\n", cur_page.borrow().key_at(i)).as_str());
-            }
-            graph_str.push_str("</TR>");
-            // print table end
-            graph_str.push_str("</TABLE>>];\n");
-            // print Leaf node link if there is a next page
-            if cur_page.borrow().get_next_page() != None {
-                graph_str.push_str(format!("{}{} -> {}{};\n{{rank=same {}{} {}{}}};\n", leaf_prefix, cur_page.borrow().get_page_id(), leaf_prefix, cur_page.borrow().get_next_page().unwrap().borrow().get_page_id(), leaf_prefix, cur_page.borrow().get_page_id(), leaf_prefix, cur_page.borrow().get_next_page().unwrap().borrow().get_page_id()).as_str());
-            }
-
-            // print parent links if there is a parent
-            if cur_page.borrow().get_parent_page() != None {
-                graph_str.push_str(format!("{}{}:p{} -> {}{};\n", internal_prefix, cur_page.borrow().get_parent_page().unwrap().borrow().get_page_id(), cur_page.borrow().get_page_id(), leaf_prefix, cur_page.borrow().get_page_id()).as_str());
+        if inserted {
+            self.len_ += 1;
+        }
+        self.check_invariants()?;
+        Ok(inserted)
+    }
+
+    fn remove_entry(&mut self, key: i32) -> Result<bool, BPlusTreeError> {
+        self.rebuild_dirty_aggregates()?;
+        let (leaf_page, path) = match self.find_leaf_path(key)? {
+            None => {
+                return Ok(false);
             }
-        } else if cur_page.borrow().is_internal_page() {
-            graph_str.push_str(format!("{}{}[shape=plain color=pink label=<<TABLE BORDER=\"0\" CELLBORDER=\"1\" CELLSPACING=\"0\" CELLPADDING=\"4\">\n", internal_prefix, cur_page.borrow().get_page_id()).as_str());
-            graph_str.push_str(format!("<TR><TD COLSPAN=\"{}\">P=\"{}\"</TD></TR>\n", cur_page.borrow().get_size(), cur_page.borrow().get_page_id()).as_str());
-            graph_str.push_str(format!("<TR><TD COLSPAN=\"{}\">max_size={},min_size={}</TD></TR>\n<TR>", cur_page.borrow().get_size(), cur_page.borrow().get_max_size(), cur_page.borrow().get_min_size()).as_str());
-
-            for i in 0..cur_page.borrow().get_size() {
-                let value;
-                match cur_page.borrow().value_at(i) {
-                    ValueType::Page(page) => {
-                        value = page.unwrap().borrow().get_page_id();
-                    }
-                    ValueType::Value(_) => {
-                        unreachable!();
+            Some(leaf_path) => {
+                leaf_path
+            }
+        };
+
+        let old_size = leaf_page.borrow().get_size();
+        let new_size = leaf_page.borrow_mut().remove_and_delete_record(key);
+
+        if old_size == new_size {
+            return Ok(false);
+        }
+
+        self.len_ -= 1;
+        if leaf_page.borrow().is_root_page() || new_size < leaf_page.borrow().get_min_size() {
+            self.coalesce_or_redistribute(leaf_page)?;
+        } else {
+            self.refresh_path(&path, -1)?;
+        }
+        self.check_invariants()?;
+        Ok(true)
+    }
+
+    /// 把已经存在的key的value改为value，返回原来的value，key不存在时什么也不做并返回None
+    pub(crate) fn update_value(&mut self, key: i32, value: i32) -> Result<Option<i32>, BPlusTreeError> {
+        self.traced(TraceOp::Update { key, value, previous: None }, |tree| tree.update_entry(key, value),
+                    |previous| TraceOp::Update { key, value, previous: *previous })
+    }
+
+    fn update_entry(&mut self, key: i32, value: i32) -> Result<Option<i32>, BPlusTreeError> {
+        self.rebuild_dirty_aggregates()?;
+        let previous = match self.find_leaf_path(key)? {
+            None => {
+                None
+            }
+            Some((leaf_page, path)) => {
+                let index = leaf_page.borrow().key_index(key);
+                if index < leaf_page.borrow().get_size() && leaf_page.borrow().key_at(index) == key {
+                    let previous = leaf_page.borrow().value_at(index);
+                    leaf_page.borrow_mut().set_value_at(index, ValueType::Value(Some(value)));
+                    self.refresh_path(&path, 0)?;
+                    match previous {
+                        ValueType::Value(previous) => previous,
+                        ValueType::Page(_) => {
+                            return Err(TreeCorruption::InvalidPage { page_id: leaf_page.borrow().get_page_id() }.into());
+                        }
                     }
+                } else {
+                    None
                 }
-                graph_str.push_str(format!("<TD PORT=\"p{}\">", value).as_str());
+            }
+        };
 
-                if i > 0 {
-                    graph_str.push_str(cur_page.borrow().key_at(i).to_string().as_str());
-                } else {
-                    graph_str.push_str(" ");
+        Ok(previous)
+    }
+
+    fn lookup_value_ref(&self, key: i32) -> Result<Option<&i32>, BPlusTreeError> {
+        let leaf_page = match self.find_leaf_page(key, Operation::FIND, false, false)? {
+            None => {
+                return Ok(None);
+            }
+            Some(leaf_page) => {
+                leaf_page
+            }
+        };
+
+        let index = leaf_page.borrow().key_index(key);
+        match self.leaf_entry_at(&leaf_page, index)? {
+            Some((found_key, value)) if *found_key == key => Ok(Some(value)),
+            _ => Ok(None)
+        }
+    }
+
+    /// 叶子节点中下标为index的键值对的引用，下标越界时返回None
+    pub(crate) fn leaf_entry_at(&self, leaf_page: &RcPage, index: usize) -> Result<Option<(&i32, &i32)>, BPlusTreeError> {
+        // SAFETY: 节点只能通过&mut self修改，返回的引用借用了self
+        let page: &BPlusTreePage = unsafe { &*leaf_page.as_ptr() };
+        match page.get_page_data().get(index) {
+            None => Ok(None),
+            Some(item) => {
+                match &item.value {
+                    ValueType::Value(Some(value)) => Ok(Some((&item.key, value))),
+                    _ => Err(TreeCorruption::InvalidPage { page_id: page.get_page_id() }.into())
                 }
+            }
+        }
+    }
 
-                graph_str.push_str("</TD>\n");
+    /// 不记录到操作日志的查找，用于其他操作内部读取原来的value
+    pub(crate) fn lookup_value(&self, key: i32) -> Result<Option<i32>, BPlusTreeError> {
+        let leaf_page = match self.find_leaf_page(key, Operation::FIND, false, false)? {
+            None => {
+                return Ok(None);
             }
+            Some(leaf_page) => {
+                leaf_page
+            }
+        };
 
-            graph_str.push_str("</TR></TABLE>>];\n");
+        let value = leaf_page.borrow().lookup(key);
+        match value {
+            ValueType::Page(_) => { Err(TreeCorruption::InvalidPage { page_id: leaf_page.borrow().get_page_id() }.into()) }
+            ValueType::Value(v) => { Ok(v) }
+        }
+    }
 
-            // print Parent link
-            if cur_page.borrow().get_parent_page() != None {
-                graph_str.push_str(format!("{}{}:p{} -> {}{};\n", internal_prefix, cur_page.borrow().get_parent_page().unwrap().borrow().get_page_id(), cur_page.borrow().get_page_id(), internal_prefix, cur_page.borrow().get_page_id()).as_str());
+    /// 不返回Result的公开方法遇到错误时直接panic
+    pub(crate) fn expect_ok<T>(&self, result: Result<T, BPlusTreeError>) -> T {
+        result.unwrap_or_else(|e| panic!("B+ tree {}: {}", self.index_name_, e))
+    }
+
+    /// 取出内部节点下标为index的孩子，指针为空或者不是内部节点时说明树已经损坏
+    pub(crate) fn child_page_at(page: &RcPage, index: usize) -> Result<RcPage, BPlusTreeError> {
+        match page.borrow().value_at(index) {
+            ValueType::Page(Some(child_page)) => Ok(child_page),
+            _ => Err(TreeCorruption::InvalidPage { page_id: page.borrow().get_page_id() }.into())
+        }
+    }
+
+    pub(crate) fn parent_page_of(page: &RcPage) -> Result<RcPage, BPlusTreeError> {
+        page.borrow().get_parent_page().ok_or_else(|| TreeCorruption::BadParentPointer { page_id: page.borrow().get_page_id() }.into())
+    }
+
+    /// 用page当前的内容更新父节点中page的count和聚合值，返回父节点，page是根节点时返回None
+    pub(crate) fn refresh_summary_in_parent(&mut self, page: &RcPage) -> Result<Page, BPlusTreeError> {
+        let parent_page = match page.borrow().get_parent_page() {
+            None => {
+                return Ok(None);
+            }
+            Some(parent_page) => {
+                parent_page
             }
+        };
 
-            // print leaves
-            for i in 0..cur_page.borrow().get_size() {
-                let child_page;
-                match cur_page.borrow().value_at(i) {
-                    ValueType::Page(page) => {
-                        child_page = page.unwrap();
-                    }
-                    ValueType::Value(_) => {
-                        unreachable!();
-                    }
+        let index = parent_page.borrow().value_index(ValueType::Page(Some(page.clone())))
+            .ok_or_else(|| TreeCorruption::BadParentPointer { page_id: page.borrow().get_page_id() })?;
+        self.refresh_summary_at(&parent_page, index, page);
+        Ok(Some(parent_page))
+    }
+
+    /// 与refresh_summary_in_parent()相同，但是已经知道page在父节点中的下标index
+    pub(crate) fn refresh_summary_at(&mut self, parent_page: &RcPage, index: usize, page: &RcPage) {
+        parent_page.borrow_mut().set_count_at(index, page.borrow().get_subtree_count());
+        if let Some(aggregator) = &self.aggregator_ {
+            let aggregate = Self::page_aggregate(aggregator.as_ref(), &page.borrow());
+            parent_page.borrow_mut().set_aggregate_at(index, aggregate);
+        }
+    }
+
+    /// 从page开始一直到根节点，依次更新路径上每个节点在父节点中的count和聚合值
+    ///
+    /// 每一层都要在父节点中查找page并重新统计count，只在split/merge等改变结构的操作中使用
+    pub(crate) fn refresh_summaries_to_root(&mut self, page: &RcPage) -> Result<(), BPlusTreeError> {
+        let mut cur_page = page.clone();
+        while let Some(parent_page) = self.refresh_summary_in_parent(&cur_page)? {
+            cur_page = parent_page;
+        }
+        Ok(())
+    }
+
+    /// 叶子节点中插入或删除了key但是没有改变树的结构时，沿着向下查找时记录的path更新count和聚合值
+    ///
+    /// count_delta为叶子节点中key个数的变化，每一层的count直接加上count_delta，
+    /// 设置了聚合函数时才需要自底向上重新计算路径上每个孩子的聚合值
+    pub(crate) fn refresh_path(&mut self, path: &[(RcPage, usize)], count_delta: isize) -> Result<(), BPlusTreeError> {
+        if count_delta != 0 {
+            for (page, index) in path {
+                let count = page.borrow().get_count_at(*index);
+                page.borrow_mut().set_count_at(*index, count.saturating_add_signed(count_delta));
+            }
+        }
+        if let Some(aggregator) = &self.aggregator_ {
+            for (page, index) in path.iter().rev() {
+                let child_page = Self::child_page_at(page, *index)?;
+                let aggregate = Self::page_aggregate(aggregator.as_ref(), &child_page.borrow());
+                page.borrow_mut().set_aggregate_at(*index, aggregate);
+            }
+        }
+        Ok(())
+    }
+
+    /// 节点中所有value的聚合值：叶子节点折叠每个value，内部节点折叠每个孩子缓存的聚合值
+    pub(crate) fn page_aggregate(aggregator: &dyn Monoid, page: &BPlusTreePage) -> i64 {
+        page.get_page_data().iter().fold(aggregator.identity(), |acc, item| {
+            let aggregate = match item.value {
+                ValueType::Value(Some(value)) => aggregator.lift(value),
+                ValueType::Value(None) => aggregator.identity(),
+                ValueType::Page(_) => item.aggregate
+            };
+            aggregator.combine(acc, aggregate)
+        })
+    }
+
+    pub(crate) fn get_merge_operator(&self) -> Option<&Rc<dyn MergeOperator>> {
+        self.merge_operator_.as_ref()
+    }
+
+    pub(crate) fn replace_merge_operator(&mut self, merge_operator: Option<Rc<dyn MergeOperator>>) {
+        self.merge_operator_ = merge_operator;
+    }
+
+    pub(crate) fn get_change_log(&self) -> Ref<'_, Option<ChangeLog>> {
+        self.change_log_.borrow()
+    }
+
+    /// 关闭原来的变更日志，capacity不为None时再打开一个新的变更日志
+    ///
+    /// 新日志的序号接着原来的日志并跳过一个序号，原来的订阅恢复时一定早于新日志中最早的序号，
+    /// 所以会收到SequenceUnavailable，而不会读到新日志中无关的事件
+    pub(crate) fn replace_change_log(&mut self, capacity: Option<SizeT>) {
+        if let Some(change_log) = self.change_log_.get_mut().take() {
+            self.next_change_sequence_ = change_log.get_next_sequence() + 1;
+        }
+        *self.change_log_.get_mut() = capacity.map(|capacity| ChangeLog::new(capacity, self.next_change_sequence_));
+    }
+
+    pub(crate) fn get_next_change_sequence(&self) -> u64 {
+        self.next_change_sequence_
+    }
+
+    /// 直接在叶子节点中插入或删除key之后，由调用者更新key的个数
+    pub(crate) fn set_len(&mut self, len: SizeT) {
+        self.len_ = len;
+    }
+
+    /// 直接替换整棵树的节点，调用者负责保证len与root_page中key的个数一致
+    pub(crate) fn replace_root(&mut self, root_page: Page, len: SizeT) {
+        self.root_page_ = root_page;
+        self.len_ = len;
+    }
+
+    pub(crate) fn get_aggregator(&self) -> Option<&Rc<dyn Monoid>> {
+        self.aggregator_.as_ref()
+    }
+
+    /// 缓存的聚合值是否可能已经过期
+    pub(crate) fn aggregates_are_dirty(&self) -> bool {
+        !self.dirty_leaves_.is_empty()
+    }
+
+    /// entries_mut()访问过的叶子节点以及它们的所有祖先节点，这些节点中缓存的聚合值可能已经过期
+    pub(crate) fn dirty_aggregate_pages(&self) -> HashSet<usize> {
+        let mut page_ids = HashSet::new();
+        for leaf_page in &self.dirty_leaves_ {
+            let mut cur_page = Some(leaf_page.clone());
+            while let Some(page) = cur_page {
+                // 祖先节点已经因为之前的叶子节点加入过了
+                if !page_ids.insert(page.borrow().get_page_id()) {
+                    break;
                 }
-                graph_str.push_str(self.to_graph(child_page.clone()).as_str());
+                cur_page = page.borrow().get_parent_page();
+            }
+        }
+        page_ids
+    }
 
-                if i > 0 {
-                    let sibling_page;
-                    match cur_page.borrow().value_at(i) {
-                        ValueType::Page(page) => {
-                            sibling_page = page.unwrap();
-                        }
-                        ValueType::Value(_) => {
-                            unreachable!();
-                        }
-                    }
+    /// 换成新的聚合函数并重新计算所有内部节点缓存的聚合值
+    pub(crate) fn replace_aggregator(&mut self, aggregator: Option<Rc<dyn Monoid>>) -> Result<(), BPlusTreeError> {
+        self.flush_value_updates();
+        self.aggregator_ = aggregator;
+        self.dirty_leaves_.clear();
+        if let (Some(aggregator), Some(root_page)) = (&self.aggregator_, &self.root_page_) {
+            Self::rebuild_page_aggregate(aggregator.as_ref(), root_page)?;
+        }
+        Ok(())
+    }
 
-                    if !sibling_page.borrow().is_leaf_page() && !child_page.borrow().is_leaf_page() {
-                        graph_str.push_str(format!("{{rank=same {}{} {}{}}};\n", internal_prefix, sibling_page.borrow().get_page_id(), internal_prefix, child_page.borrow().get_page_id()).as_str());
+    /// 修改树之前调用，处理entries_mut()留下的过期的聚合值和还没有记录的value修改
+    ///
+    /// 只重新计算entries_mut()访问过的叶子节点到根节点路径上的聚合值，每一层中同一个节点只计算一次
+    pub(crate) fn rebuild_dirty_aggregates(&mut self) -> Result<(), BPlusTreeError> {
+        self.flush_value_updates();
+        let mut dirty_pages = mem::take(&mut self.dirty_leaves_);
+        // 所有叶子节点的深度相同，所以逐层向上计算时孩子总是先于父节点更新
+        while !dirty_pages.is_empty() {
+            let mut parent_pages = Vec::new();
+            let mut parent_page_ids = HashSet::new();
+            for page in dirty_pages {
+                if let Some(parent_page) = self.refresh_summary_in_parent(&page)? {
+                    if parent_page_ids.insert(parent_page.borrow().get_page_id()) {
+                        parent_pages.push(parent_page);
                     }
                 }
             }
+            dirty_pages = parent_pages;
         }
+        Ok(())
+    }
 
-        graph_str
+    /// 自底向上重新计算子树中每个内部节点缓存的聚合值，返回整棵子树的聚合值
+    fn rebuild_page_aggregate(aggregator: &dyn Monoid, page: &RcPage) -> Result<i64, BPlusTreeError> {
+        if page.borrow().is_internal_page() {
+            let size = page.borrow().get_size();
+            for i in 0..size {
+                let child_page = Self::child_page_at(page, i)?;
+                let aggregate = Self::rebuild_page_aggregate(aggregator, &child_page)?;
+                page.borrow_mut().set_aggregate_at(i, aggregate);
+            }
+        }
+        Ok(Self::page_aggregate(aggregator, &page.borrow()))
     }
 
-    fn to_string(&self, cur_page: RcPage) -> String {
-        let page_id = cur_page.borrow().get_page_id();
-        let parent_page_id;
-        let next_page_id;
+    /// 是否有操作日志或者变更日志需要trace()记录的操作，批量操作只在需要时才逐个列出修改的key
+    pub(crate) fn is_tracing(&self) -> bool {
+        self.tracer_.borrow().is_some() || self.change_log_.borrow().is_some()
+    }
 
-        if cur_page.borrow().get_parent_page().is_some() {
-            parent_page_id = cur_page.borrow().get_parent_page().as_ref().unwrap().borrow().get_page_id().to_string();
-        } else {
-            parent_page_id = String::from("None");
+    /// 记录一条已经执行完的操作
+    pub(crate) fn trace(&self, op: TraceOp) {
+        self.flush_value_updates();
+        self.record_op(op);
+    }
+
+    /// 在执行操作之前记录参数，操作执行完之后必须调用trace_end()
+    pub(crate) fn trace_begin(&self, op: TraceOp) {
+        self.flush_value_updates();
+        if let Some(tracer) = self.tracer_.borrow_mut().as_mut() {
+            tracer.begin(op);
         }
+    }
 
-        if cur_page.borrow().get_next_page().is_some() {
-            next_page_id = cur_page.borrow().get_next_page().as_ref().unwrap().borrow().get_page_id().to_string();
-        } else {
-            next_page_id = String::from("None");
+    /// 记录trace_begin()开始的操作的结果，result为None表示操作返回了错误，只有执行完的操作会进入变更日志
+    pub(crate) fn trace_end(&self, result: Option<TraceOp>) {
+        if let Some(tracer) = self.tracer_.borrow_mut().as_mut() {
+            tracer.end(result);
         }
+        if let (Some(op), Some(change_log)) = (result, self.change_log_.borrow_mut().as_mut()) {
+            change_log.record(op);
+        }
+    }
 
-        if cur_page.borrow().is_leaf_page() {
-            let mut leaf_str = format!("Leaf Page: {} Parent: {} Next: {}\n", page_id, parent_page_id, next_page_id);
+    /// 先记录op的参数再执行run，之后记录result_op给出的结果或者run返回的错误，run中panic时op被记录为没有执行完
+    pub(crate) fn traced<T>(&mut self, op: TraceOp, run: impl FnOnce(&mut Self) -> Result<T, BPlusTreeError>,
+                            result_op: impl FnOnce(&T) -> TraceOp) -> Result<T, BPlusTreeError> {
+        self.trace_begin(op);
+        let result = run(self);
+        self.trace_end(result.as_ref().ok().map(result_op));
+        result
+    }
+
+    /// 批量操作在日志中展开为逐个key的操作ops：执行之前先记录第一条操作的参数，
+    /// 执行成功之后再记录其余的操作，失败时第一条操作被记录为返回了错误
+    pub(crate) fn traced_expanded<T>(&mut self, ops: Vec<TraceOp>, run: impl FnOnce(&mut Self) -> Result<T, BPlusTreeError>) -> Result<T, BPlusTreeError> {
+        let mut ops = ops.into_iter();
+        let first_op = match ops.next() {
+            None => {
+                return run(self);
+            }
+            Some(first_op) => {
+                first_op
+            }
+        };
 
-            for i in 0..cur_page.borrow().get_size() {
-                let key = cur_page.borrow().key_at(i);
-                leaf_str.push_str(format!("{}, ", key).as_str());
+        let result = self.traced(first_op, run, |_| first_op);
+        if result.is_ok() {
+            for op in ops {
+                self.trace(op);
             }
+        }
+        result
+    }
 
-            leaf_str.push_str("\n\n");
+    /// 把entries_mut()之后修改过的value按key从小到大记录为update
+    pub(crate) fn flush_value_updates(&self) {
+        let snapshot = match self.value_snapshot_.take() {
+            None => {
+                return;
+            }
+            Some(snapshot) => {
+                snapshot
+            }
+        };
+
+        let mut snapshot = snapshot.into_iter().peekable();
+        for (key, value) in self.entries() {
+            while snapshot.next_if(|(previous_key, _)| previous_key < key).is_some() {}
+            if let Some((_, previous)) = snapshot.next_if(|(previous_key, _)| previous_key == key) {
+                if previous != *value {
+                    self.record_op(TraceOp::Update { key: *key, value: *value, previous: Some(previous) });
+                }
+            }
+        }
+    }
 
-            leaf_str
-        } else if cur_page.borrow().is_internal_page() {
-            let mut internal_str = format!("Internal Page: {} Parent: {} Next: {}\n", page_id, parent_page_id, next_page_id);
+    fn record_op(&self, op: TraceOp) {
+        if let Some(tracer) = self.tracer_.borrow_mut().as_mut() {
+            tracer.record(op);
+        }
+        if let Some(change_log) = self.change_log_.borrow_mut().as_mut() {
+            change_log.record(op);
+        }
+    }
 
-            for i in 0..cur_page.borrow().get_size() {
-                let key = cur_page.borrow().key_at(i);
+    /// 拼接或拆分整棵树时借用的split、coalesce和redistribute不是插入或删除引起的，
+    /// 运行run期间的这些操作不计入统计次数，也不录制帧
+    pub(crate) fn without_structural_stats<T>(&mut self, run: impl FnOnce(&mut Self) -> Result<T, BPlusTreeError>) -> Result<T, BPlusTreeError> {
+        let counts = (self.split_count_, self.merge_count_, self.redistribute_count_);
+        let recorder = self.recorder_.take();
+        let result = run(self);
+        (self.split_count_, self.merge_count_, self.redistribute_count_) = counts;
+        self.recorder_ = recorder;
+        result
+    }
 
-                match cur_page.borrow().value_at(i) {
-                    ValueType::Page(child_page) => {
-                        let value = child_page.as_ref().unwrap().borrow().get_page_id();
-                        internal_str.push_str(format!("{} : {}, ", key, value).as_str());
-                    }
-                    ValueType::Value(value) => {
-                        let value = match value {
-                            None => {
-                                String::from("-")
-                            }
-                            Some(v) => {
-                                v.to_string()
-                            }
-                        };
-                        internal_str.push_str(format!("{} : {}, ", key, value).as_str());
-                    }
+    fn record_frame(&mut self, event: StructuralEvent, changed_pages: &[&RcPage]) {
+        if let Some(mut recorder) = self.recorder_.take() {
+            let changed_page_ids = changed_pages.iter().map(|page| page.borrow().get_page_id()).collect();
+            recorder.record(self, event, changed_page_ids);
+            self.recorder_ = Some(recorder);
+        }
+    }
+
+    pub(crate) fn check_invariants(&self) -> Result<(), BPlusTreeError> {
+        if cfg!(debug_assertions) && self.check_invariants_ {
+            self.validate()?;
+        }
+        Ok(())
+    }
+
+    /// 返回子树中key的个数和所有value的聚合值，没有设置聚合函数时聚合值为0
+    fn validate_page(&self, cur_page: RcPage, lower: Option<i32>, upper: Option<i32>, depth: usize,
+                     leaf_depth: &mut Option<usize>, leaf_pages: &mut Vec<RcPage>) -> Result<(SizeT, i64), TreeCorruption> {
+        let page = cur_page.borrow();
+        let page_id = page.get_page_id();
+        let size = page.get_size();
+
+        // 叶子节点在插入后达到max_size就会分裂，所以最多只能保存max_size - 1个元素
+        let max_size = if page.is_leaf_page() {
+            page.get_max_size() - 1
+        } else if page.is_internal_page() {
+            page.get_max_size()
+        } else {
+            return Err(TreeCorruption::InvalidPage { page_id });
+        };
+
+        if size > max_size {
+            return Err(TreeCorruption::Overflow { page_id, size, max_size });
+        }
+        if size < page.get_min_size() {
+            return Err(TreeCorruption::Underflow { page_id, size, min_size: page.get_min_size() });
+        }
+
+        // 内部节点下标为0的key不提供检索功能
+        let first_key_index = if page.is_internal_page() { 1 } else { 0 };
+        for i in first_key_index..size {
+            let key = page.key_at(i);
+            if i > first_key_index && page.key_at(i - 1) >= key {
+                return Err(TreeCorruption::UnsortedKeys { page_id, index: i });
+            }
+            if lower.is_some_and(|lower| key < lower) || upper.is_some_and(|upper| key >= upper) {
+                return Err(TreeCorruption::KeyOutOfRange { page_id, key, lower, upper });
+            }
+        }
+
+        if page.is_leaf_page() {
+            match *leaf_depth {
+                None => {
+                    *leaf_depth = Some(depth);
+                }
+                Some(expected) if expected != depth => {
+                    return Err(TreeCorruption::UnevenLeafDepth { page_id, depth, expected });
+                }
+                Some(_) => {}
+            }
+            for i in 0..size {
+                if page.get_count_at(i) != 1 {
+                    return Err(TreeCorruption::BadSubtreeCount { page_id, index: i, count: page.get_count_at(i), expected: 1 });
                 }
             }
+            leaf_pages.push(cur_page.clone());
+            let aggregate = self.aggregator_.as_ref().map_or(0, |aggregator| Self::page_aggregate(aggregator.as_ref(), &page));
+            return Ok((size, aggregate));
+        }
 
-            internal_str.push_str("\n\n");
+        let mut subtree_count = 0;
+        // entries_mut()之后缓存的聚合值可能已经过期，等下次修改树时才会重新计算
+        let aggregator = self.aggregator_.as_ref().filter(|_| !self.aggregates_are_dirty());
+        let mut subtree_aggregate = aggregator.map_or(0, |aggregator| aggregator.identity());
+        for i in 0..size {
+            let child_page = match page.value_at(i) {
+                ValueType::Page(Some(child_page)) => {
+                    child_page
+                }
+                _ => {
+                    return Err(TreeCorruption::InvalidPage { page_id });
+                }
+            };
 
-            for i in 0..cur_page.borrow().get_size() {
-                if let ValueType::Page(child_page) = cur_page.borrow().value_at(i) {
-                    let child_page = child_page.unwrap();
-                    internal_str.push_str(self.to_string(child_page).as_str());
-                } else {
-                    unreachable!();
+            let parent_page = child_page.borrow().get_parent_page();
+            if parent_page.is_none_or(|parent_page| parent_page.borrow().get_page_id() != page_id) {
+                return Err(TreeCorruption::BadParentPointer { page_id: child_page.borrow().get_page_id() });
+            }
+
+            // 下标为i的子树中的所有key满足 key(i) <= subtree(value(i)) < key(i+1)
+            let child_lower = if i == 0 { lower } else { Some(page.key_at(i)) };
+            let child_upper = if i + 1 < size { Some(page.key_at(i + 1)) } else { upper };
+            let (child_count, child_aggregate) = self.validate_page(child_page, child_lower, child_upper, depth + 1, leaf_depth, leaf_pages)?;
+            if page.get_count_at(i) != child_count {
+                return Err(TreeCorruption::BadSubtreeCount { page_id, index: i, count: page.get_count_at(i), expected: child_count });
+            }
+            subtree_count += child_count;
+            if let Some(aggregator) = aggregator {
+                if page.get_aggregate_at(i) != child_aggregate {
+                    return Err(TreeCorruption::BadSubtreeAggregate { page_id, index: i, aggregate: page.get_aggregate_at(i), expected: child_aggregate });
                 }
+                subtree_aggregate = aggregator.combine(subtree_aggregate, child_aggregate);
             }
+        }
 
-            internal_str
-        } else {
-            String::new()
+        Ok((subtree_count, subtree_aggregate))
+    }
+
+    fn validate_leaf_chain(&self, leaf_pages: &[RcPage]) -> Result<(), TreeCorruption> {
+        for (i, leaf_page) in leaf_pages.iter().enumerate() {
+            let next_page_id = leaf_page.borrow().get_next_page().map(|next_page| next_page.borrow().get_page_id());
+            let expected_page_id = leaf_pages.get(i + 1).map(|next_page| next_page.borrow().get_page_id());
+            if next_page_id != expected_page_id {
+                return Err(TreeCorruption::BrokenLeafChain { page_id: leaf_page.borrow().get_page_id() });
+            }
         }
+        Ok(())
     }
 
-    fn find_leaf_page(&self, key: i32, operation: Operation, left_most: bool, right_most: bool) -> Page {
-        if self.root_page_.is_none() {
-            return None
+    /// 树为空时返回Ok(None)
+    pub(crate) fn find_leaf_page(&self, key: i32, operation: Operation, left_most: bool, right_most: bool) -> Result<Page, BPlusTreeError> {
+        match &self.root_page_ {
+            None => Ok(None),
+            Some(root_page) => Self::find_leaf_page_from(root_page.clone(), key, operation, left_most, right_most).map(Some)
         }
+    }
 
-        let mut cur_page = self.root_page_.clone().unwrap();
+    /// 与find_leaf_page()相同，同时返回经过的每个内部节点以及选中的孩子下标，树为空时返回Ok(None)
+    pub(crate) fn find_leaf_path(&self, key: i32) -> Result<Option<(RcPage, SearchPath)>, BPlusTreeError> {
+        let mut cur_page = match &self.root_page_ {
+            None => {
+                return Ok(None);
+            }
+            Some(root_page) => {
+                root_page.clone()
+            }
+        };
 
+        let mut path = SearchPath::new();
         while cur_page.borrow().is_internal_page() {
-            let child_page;
-            if left_most {
-                child_page = cur_page.borrow().value_at(0);
+            let (index, _) = cur_page.borrow().lookup_traced(key, |_, _| {});
+            let index = index.ok_or_else(|| TreeCorruption::InvalidPage { page_id: cur_page.borrow().get_page_id() })?;
+            let child_page = Self::child_page_at(&cur_page, index)?;
+            path.push((cur_page, index));
+            cur_page = child_page;
+        }
+
+        if !cur_page.borrow().is_leaf_page() {
+            return Err(TreeCorruption::InvalidPage { page_id: cur_page.borrow().get_page_id() }.into());
+        }
+        Ok(Some((cur_page, path)))
+    }
+
+    /// 与find_leaf_page()相同，但是从cur_page开始向下查找
+    pub(crate) fn find_leaf_page_from(mut cur_page: RcPage, key: i32, operation: Operation, left_most: bool, right_most: bool) -> Result<RcPage, BPlusTreeError> {
+        while cur_page.borrow().is_internal_page() {
+            let child_page = if left_most {
+                cur_page.borrow().value_at(0)
             } else if right_most {
-                child_page = cur_page.borrow().value_at(cur_page.borrow().get_size() - 1);
+                cur_page.borrow().value_at(cur_page.borrow().get_size() - 1)
             } else {
-                child_page = cur_page.borrow().lookup(key);
-            }
-            assert!(child_page != ValueType::Page(None));
+                cur_page.borrow().lookup(key)
+            };
 
             match operation {
                 Operation::FIND => {
@@ -312,14 +1074,20 @@ impl BPlusTree {
                 }
             }
 
-            if let ValueType::Page(page) = child_page {
-                cur_page = page.unwrap();
-            } else {
-                unreachable!();
-            }
+            cur_page = match child_page {
+                ValueType::Page(Some(page)) => {
+                    page
+                }
+                _ => {
+                    return Err(TreeCorruption::InvalidPage { page_id: cur_page.borrow().get_page_id() }.into());
+                }
+            };
         }
 
-        Some(cur_page)
+        if !cur_page.borrow().is_leaf_page() {
+            return Err(TreeCorruption::InvalidPage { page_id: cur_page.borrow().get_page_id() }.into());
+        }
+        Ok(cur_page)
     }
 
     fn create_new_tree(&mut self, key: i32, value: i32) {
@@ -328,79 +1096,129 @@ impl BPlusTree {
         self.root_page_ = Some(new_root);
     }
 
-    fn insert_into_leaf(&mut self, key: i32, value: i32) -> bool {
-        let leaf_page = self.find_leaf_page(key, Operation::INSERT, false, false);
-        if leaf_page.is_none() {
-            return false;
-        }
+    fn insert_into_leaf(&mut self, key: i32, value: i32) -> Result<bool, BPlusTreeError> {
+        let (leaf_page, path) = match self.find_leaf_path(key)? {
+            None => {
+                return Ok(false);
+            }
+            Some(leaf_path) => {
+                leaf_path
+            }
+        };
 
-        let leaf_page = leaf_page.unwrap();
         let old_size = leaf_page.borrow().get_size();
         let new_size = leaf_page.borrow_mut().insert(key, value);
 
         if old_size == new_size {
-            return false;
+            return Ok(false);
         }
 
-        if leaf_page.borrow().is_internal_page() {
-            if new_size <= self.leaf_max_size_ {
-                return true;
-            }
-        } else if leaf_page.borrow().is_leaf_page() {
-            if new_size < self.leaf_max_size_ {
-                return true;
-            }
+        if new_size < self.leaf_max_size_ {
+            self.refresh_path(&path, 1)?;
+            return Ok(true);
         }
 
-        let sibling_leaf_page = self.split(leaf_page.clone()).unwrap();
+        let sibling_leaf_page = self.split(leaf_page.clone())?;
         sibling_leaf_page.borrow_mut().set_next_page(leaf_page.borrow().get_next_page());
         leaf_page.borrow_mut().set_next_page(Some(sibling_leaf_page.clone()));
         let middle_key = sibling_leaf_page.borrow().key_at(0);
 
-        self.insert_into_parent(leaf_page.clone(), middle_key, sibling_leaf_page.clone());
+        self.insert_into_path(&path, leaf_page.clone(), middle_key, sibling_leaf_page.clone())?;
 
-        true
+        Ok(true)
     }
 
-    fn split(&mut self, cur_page: RcPage) -> Page {
-        if cur_page.borrow().is_internal_page() {
-            let new_page = BPlusTreePage::new(InternalPage, self.internal_max_size_, cur_page.borrow().get_parent_page());
-            cur_page.borrow_mut().move_half_to(new_page.clone());
-            return Some(new_page);
+    pub(crate) fn split(&mut self, cur_page: RcPage) -> Result<RcPage, BPlusTreeError> {
+        let new_page = if cur_page.borrow().is_internal_page() {
+            BPlusTreePage::new(InternalPage, self.internal_max_size_, cur_page.borrow().get_parent_page())
         } else if cur_page.borrow().is_leaf_page() {
-            let new_page = BPlusTreePage::new(LeafPage, self.leaf_max_size_, cur_page.borrow().get_parent_page());
-            cur_page.borrow_mut().move_half_to(new_page.clone());
-            return Some(new_page);
-        }
-        None
+            BPlusTreePage::new(LeafPage, self.leaf_max_size_, cur_page.borrow().get_parent_page())
+        } else {
+            return Err(TreeCorruption::InvalidPage { page_id: cur_page.borrow().get_page_id() }.into());
+        };
+
+        self.split_count_ += 1;
+        cur_page.borrow_mut().move_half_to(new_page.clone())?;
+        Ok(new_page)
     }
 
-    fn insert_into_parent(&mut self, old_page: RcPage, middle_key: i32, new_page: RcPage) {
+    pub(crate) fn insert_into_parent(&mut self, old_page: RcPage, middle_key: i32, new_page: RcPage) -> Result<(), BPlusTreeError> {
+        let event = Self::split_event(&old_page);
         if old_page.borrow().is_root_page() {
             let new_root = BPlusTreePage::new(InternalPage, self.internal_max_size_, None);
             new_root.borrow_mut().create_new_root(old_page.clone(), middle_key, new_page.clone());
             old_page.borrow_mut().set_parent_page(Some(new_root.clone()));
             new_page.borrow_mut().set_parent_page(Some(new_root.clone()));
-            self.root_page_ = Some(new_root);
-            return;
+            self.root_page_ = Some(new_root.clone());
+            self.refresh_summary_in_parent(&old_page)?;
+            self.refresh_summary_in_parent(&new_page)?;
+            self.record_frame(event, &[&old_page, &new_page, &new_root]);
+            return Ok(());
         }
 
-        let parent_page = old_page.borrow().get_parent_page().unwrap();
+        let parent_page = Self::parent_page_of(&old_page)?;
+        let old_size = parent_page.borrow().get_size();
         let new_size = parent_page.borrow_mut().insert_node_after(old_page.clone(), middle_key, new_page.clone());
-        // TODO
+        // 父节点中找不到old_page时insert_node_after什么也不做
+        if new_size == old_size {
+            return Err(TreeCorruption::BadParentPointer { page_id: old_page.borrow().get_page_id() }.into());
+        }
+        new_page.borrow_mut().set_parent_page(Some(parent_page.clone()));
+        self.refresh_summary_in_parent(&old_page)?;
+        self.refresh_summary_in_parent(&new_page)?;
+        self.record_frame(event, &[&old_page, &new_page, &parent_page]);
+
+        // -1是去掉下标为0的item
+        if new_size - 1 < self.internal_max_size_ {
+            // 父节点以上的count和聚合值还需要更新
+            return self.refresh_summaries_to_root(&parent_page);
+        }
+
+        let new_parent_sibling_node = self.split(parent_page.clone())?;
+        let middle_key = new_parent_sibling_node.borrow().key_at(0);
+        self.insert_into_parent(parent_page.clone(), middle_key, new_parent_sibling_node.clone())
+    }
+
+    /// 与insert_into_parent()相同，但是父节点以及old_page在父节点中的下标由向下查找时记录的path给出，
+    /// 父节点没有分裂时，更上面的祖先节点只需要把count加1，不用在每一层查找old_page
+    fn insert_into_path(&mut self, path: &[(RcPage, usize)], old_page: RcPage, middle_key: i32, new_page: RcPage) -> Result<(), BPlusTreeError> {
+        let ((parent_page, index), ancestors) = match path.split_last() {
+            None => {
+                return self.insert_into_parent(old_page, middle_key, new_page);
+            }
+            Some(last) => {
+                last
+            }
+        };
+
+        let event = Self::split_event(&old_page);
+        let new_size = parent_page.borrow_mut().insert_node_at(index + 1, middle_key, new_page.clone());
         new_page.borrow_mut().set_parent_page(Some(parent_page.clone()));
+        self.refresh_summary_at(parent_page, *index, &old_page);
+        self.refresh_summary_at(parent_page, index + 1, &new_page);
+        self.record_frame(event, &[&old_page, &new_page, parent_page]);
 
         // -1是去掉下标为0的item
         if new_size - 1 < self.internal_max_size_ {
-            return;
+            return self.refresh_path(ancestors, 1);
         }
 
-        let new_parent_sibling_node = self.split(parent_page.clone()).unwrap();
+        let new_parent_sibling_node = self.split(parent_page.clone())?;
         let middle_key = new_parent_sibling_node.borrow().key_at(0);
-        self.insert_into_parent(parent_page.clone(), middle_key, new_parent_sibling_node.clone());
+        self.insert_into_path(ancestors, parent_page.clone(), middle_key, new_parent_sibling_node)
     }
 
-    fn coalesce(&mut self, neighbor_page: &mut RcPage, cur_page: &mut RcPage, parent_page: RcPage, index: usize) -> bool {
+    /// 新节点插入父节点之后才能从根节点访问到，所以分裂的这一帧在插入父节点时录制
+    fn split_event(old_page: &RcPage) -> StructuralEvent {
+        if old_page.borrow().is_leaf_page() {
+            StructuralEvent::LeafSplit
+        } else {
+            StructuralEvent::InsertIntoParent
+        }
+    }
+
+    fn coalesce(&mut self, neighbor_page: &mut RcPage, cur_page: &mut RcPage, parent_page: RcPage, index: usize) -> Result<bool, BPlusTreeError> {
+        self.merge_count_ += 1;
         let mut key_index = index;
 
         if index == 0 {
@@ -409,34 +1227,42 @@ impl BPlusTree {
         }
 
         let middle_key = parent_page.borrow().key_at(key_index);
-        cur_page.borrow_mut().move_all_to((*neighbor_page).clone(), middle_key);
+        cur_page.borrow_mut().move_all_to((*neighbor_page).clone(), middle_key)?;
         (*neighbor_page).borrow_mut().set_next_page(cur_page.borrow().get_next_page());
 
         parent_page.borrow_mut().remove(key_index);
-        return self.coalesce_or_redistribute(parent_page.clone());
+        // parent_page本身的count由下面对parent_page的coalesce_or_redistribute负责更新
+        self.refresh_summary_in_parent(neighbor_page)?;
+        self.record_frame(StructuralEvent::Coalesce, &[neighbor_page, &parent_page]);
+        self.coalesce_or_redistribute(parent_page.clone())
     }
 
-    fn redistribute(&mut self, neighbor_page: RcPage, cur_page: RcPage, parent_page: RcPage, index: usize) {
+    fn redistribute(&mut self, neighbor_page: RcPage, cur_page: RcPage, parent_page: RcPage, index: usize) -> Result<(), BPlusTreeError> {
+        self.redistribute_count_ += 1;
         if cur_page.borrow().is_leaf_page() {
             if index == 0 {
-                neighbor_page.borrow_mut().move_first_to_end_of(cur_page, 0);
+                neighbor_page.borrow_mut().move_first_to_end_of(cur_page.clone(), 0)?;
                 parent_page.borrow_mut().set_key_at(1, neighbor_page.borrow().key_at(0));
             } else {
-                neighbor_page.borrow_mut().move_last_to_front_of(cur_page, 0);
-                parent_page.borrow_mut().set_key_at(index, neighbor_page.borrow().key_at(0));
+                neighbor_page.borrow_mut().move_last_to_front_of(cur_page.clone(), 0)?;
+                parent_page.borrow_mut().set_key_at(index, cur_page.borrow().key_at(0));
             }
         } else if cur_page.borrow().is_internal_page() {
             if index == 0 {
-                neighbor_page.borrow_mut().move_first_to_end_of(cur_page, parent_page.borrow().key_at(1));
+                neighbor_page.borrow_mut().move_first_to_end_of(cur_page.clone(), parent_page.borrow().key_at(1))?;
                 parent_page.borrow_mut().set_key_at(1, neighbor_page.borrow().key_at(0));
             } else {
-                neighbor_page.borrow_mut().move_last_to_front_of(cur_page, parent_page.borrow().key_at(index));
-                parent_page.borrow_mut().set_key_at(index, neighbor_page.borrow().key_at(0));
+                neighbor_page.borrow_mut().move_last_to_front_of(cur_page.clone(), parent_page.borrow().key_at(index))?;
+                parent_page.borrow_mut().set_key_at(index, cur_page.borrow().key_at(0));
             }
         }
+        self.refresh_summary_in_parent(&neighbor_page)?;
+        self.refresh_summaries_to_root(&cur_page)?;
+        self.record_frame(StructuralEvent::Redistribute, &[&neighbor_page, &cur_page, &parent_page]);
+        Ok(())
     }
 
-    fn coalesce_or_redistribute(&mut self, cur_page: RcPage) -> bool {
+    pub(crate) fn coalesce_or_redistribute(&mut self, cur_page: RcPage) -> Result<bool, BPlusTreeError> {
         if cur_page.borrow().is_root_page() {
             return self.adjust_root(cur_page);
         }
@@ -445,45 +1271,48 @@ impl BPlusTree {
         let min_size = cur_page.borrow().get_min_size();
 
         if cur_size >= min_size {
-            return false;
+            self.refresh_summaries_to_root(&cur_page)?;
+            return Ok(false);
         }
 
-        let parent_page = cur_page.borrow().get_parent_page().unwrap();
-        let cur_page_index = parent_page.borrow().value_index( ValueType::Page(Some(cur_page.clone()))).unwrap();
+        let parent_page = Self::parent_page_of(&cur_page)?;
+        let cur_page_index = parent_page.borrow().value_index(ValueType::Page(Some(cur_page.clone())))
+            .ok_or_else(|| TreeCorruption::BadParentPointer { page_id: cur_page.borrow().get_page_id() })?;
 
-        let mut sibling_page: RcPage = BPlusTreePage::new(InvalidIndexPage, 0, None);
-
-        if cur_page_index == 0 {
-            if let ValueType::Page(sibling) = parent_page.borrow().value_at(1) {
-                sibling_page = sibling.unwrap().clone();
-            }
-        } else {
-            if let ValueType::Page(sibling) = parent_page.borrow().value_at(cur_page_index - 1) {
-                sibling_page = sibling.unwrap().clone();
-            }
-        }
+        // 优先选择左边的兄弟节点，当前节点是最左边的孩子时选择右边的兄弟节点
+        let sibling_index = if cur_page_index == 0 { 1 } else { cur_page_index - 1 };
+        let sibling_page = Self::child_page_at(&parent_page, sibling_index)?;
 
         let coalesce_size = cur_page.borrow().get_size() + sibling_page.borrow().get_size();
-        let max_size = cur_page.borrow().get_max_size();
+        // 叶子节点最多只能保存max_size - 1个元素，合并后不能达到max_size
+        let max_size = if cur_page.borrow().is_leaf_page() {
+            cur_page.borrow().get_max_size() - 1
+        } else {
+            cur_page.borrow().get_max_size()
+        };
 
         if coalesce_size > max_size {
-            self.redistribute(sibling_page.clone(), cur_page, parent_page.clone(), cur_page_index);
-            return false;
+            self.redistribute(sibling_page.clone(), cur_page, parent_page.clone(), cur_page_index)?;
+            return Ok(false);
         }
 
-        let _ = self.coalesce(&mut sibling_page.clone(), &mut cur_page.clone(), parent_page.clone(), cur_page_index);
-        return true;
+        self.coalesce(&mut sibling_page.clone(), &mut cur_page.clone(), parent_page.clone(), cur_page_index)?;
+        Ok(true)
     }
 
-    fn adjust_root(&mut self, old_root_page: RcPage) -> bool {
+    fn adjust_root(&mut self, old_root_page: RcPage) -> Result<bool, BPlusTreeError> {
         if old_root_page.borrow().is_internal_page() && old_root_page.borrow().get_size() == 1 {
-            if let ValueType::Page(only_child_page) = old_root_page.borrow().value_at(0) {
-                let only_child_page = only_child_page.unwrap();
-                only_child_page.borrow_mut().set_parent_page(None);
-                self.root_page_ = Some(only_child_page.clone());
-                return true;
-            }
+            let only_child_page = Self::child_page_at(&old_root_page, 0)?;
+            only_child_page.borrow_mut().set_parent_page(None);
+            self.root_page_ = Some(only_child_page.clone());
+            self.record_frame(StructuralEvent::AdjustRoot, &[&only_child_page]);
+            return Ok(true);
+        }
+        if old_root_page.borrow().is_leaf_page() && old_root_page.borrow().get_size() == 0 {
+            self.root_page_ = None;
+            self.record_frame(StructuralEvent::AdjustRoot, &[]);
+            return Ok(true);
         }
-        old_root_page.borrow().is_leaf_page() && old_root_page.borrow().get_size() == 0
+        Ok(false)
     }
 }
\ No newline at end of file
@@ -0,0 +1,159 @@
+//! 区间聚合查询
+//!
+//! 设置聚合函数之后，内部节点的每个元素除了子树中key的个数之外，还会缓存子树中所有value的聚合值，
+//! 插入、删除、split、merge和redistribute时都会沿着修改过的路径更新，所以aggregate()只需要沿着区间的
+//! 左右边界向下查找两次，中间完整覆盖的子树直接使用缓存的聚合值，时间复杂度为O(log n)
+
+use std::collections::HashSet;
+use std::ops::{Bound, RangeBounds};
+use std::rc::Rc;
+use crate::index::b_plus_tree::{BPlusTree, BPlusTreeError};
+use crate::page::b_plus_tree_page::{RcPage, ValueType};
+
+/// 满足结合律的聚合函数，identity()与任意聚合值combine之后不改变该值
+///
+/// 聚合值统一用i64表示，这样求和时不会因为i32溢出
+pub trait Monoid {
+    fn identity(&self) -> i64;
+
+    /// 把一个value转换成聚合值
+    fn lift(&self, value: i32) -> i64;
+
+    fn combine(&self, a: i64, b: i64) -> i64;
+}
+
+/// value之和，区间为空时为0
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Sum;
+
+impl Monoid for Sum {
+    fn identity(&self) -> i64 {
+        0
+    }
+
+    fn lift(&self, value: i32) -> i64 {
+        value as i64
+    }
+
+    fn combine(&self, a: i64, b: i64) -> i64 {
+        a.wrapping_add(b)
+    }
+}
+
+/// value的最小值，区间为空时为i64::MAX
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Min;
+
+impl Monoid for Min {
+    fn identity(&self) -> i64 {
+        i64::MAX
+    }
+
+    fn lift(&self, value: i32) -> i64 {
+        value as i64
+    }
+
+    fn combine(&self, a: i64, b: i64) -> i64 {
+        a.min(b)
+    }
+}
+
+/// value的最大值，区间为空时为i64::MIN
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Max;
+
+impl Monoid for Max {
+    fn identity(&self) -> i64 {
+        i64::MIN
+    }
+
+    fn lift(&self, value: i32) -> i64 {
+        value as i64
+    }
+
+    fn combine(&self, a: i64, b: i64) -> i64 {
+        a.max(b)
+    }
+}
+
+impl BPlusTree {
+    /// 设置聚合函数，会替换之前的聚合函数并重新计算所有内部节点缓存的聚合值，时间复杂度为O(n)
+    pub fn set_aggregator(&mut self, aggregator: impl Monoid + 'static) {
+        let result = self.replace_aggregator(Some(Rc::new(aggregator)));
+        self.expect_ok(result)
+    }
+
+    /// 取消聚合函数，之后修改树时不再维护聚合值
+    pub fn remove_aggregator(&mut self) {
+        let result = self.replace_aggregator(None);
+        self.expect_ok(result)
+    }
+
+    /// range中所有value的聚合值，没有设置聚合函数时返回None
+    ///
+    /// entries_mut()修改过value之后，访问过的叶子节点到根节点路径上缓存的聚合值要等到下一次修改树时才重新计算，
+    /// 在这之前aggregate()遇到这些路径上的子树时向下重新计算，其余子树仍然使用缓存的聚合值
+    pub fn aggregate(&self, range: impl RangeBounds<i32>) -> Option<i64> {
+        let aggregator = self.get_aggregator()?.as_ref();
+        // 转换成左闭右开的区间[start, end)，用i64表示避免i32::MAX + 1溢出
+        let start = match range.start_bound() {
+            Bound::Included(start) => *start as i64,
+            Bound::Excluded(start) => *start as i64 + 1,
+            Bound::Unbounded => i64::MIN
+        };
+        let end = match range.end_bound() {
+            Bound::Included(end) => *end as i64 + 1,
+            Bound::Excluded(end) => *end as i64,
+            Bound::Unbounded => i64::MAX
+        };
+
+        let dirty_pages = self.dirty_aggregate_pages();
+        let aggregate = match self.get_root_page() {
+            None => aggregator.identity(),
+            Some(root_page) => {
+                let result = Self::aggregate_page(aggregator, &dirty_pages, &root_page, i64::MIN, i64::MAX, start, end);
+                self.expect_ok(result)
+            }
+        };
+        Some(aggregate)
+    }
+}
+
+impl BPlusTree {
+    /// page中所有的key都在[lower, upper)中，返回page中key在[start, end)中的value的聚合值
+    ///
+    /// dirty_pages中的子树即使完整覆盖在区间中也不能使用缓存的聚合值
+    fn aggregate_page(aggregator: &dyn Monoid, dirty_pages: &HashSet<usize>, page: &RcPage, lower: i64, upper: i64, start: i64, end: i64)
+                      -> Result<i64, BPlusTreeError> {
+        if page.borrow().is_leaf_page() {
+            let page = page.borrow();
+            let aggregate = page.get_page_data().iter()
+                .filter(|item| start <= item.key as i64 && (item.key as i64) < end)
+                .fold(aggregator.identity(), |acc, item| match item.value {
+                    ValueType::Value(Some(value)) => aggregator.combine(acc, aggregator.lift(value)),
+                    _ => acc
+                });
+            return Ok(aggregate);
+        }
+
+        let size = page.borrow().get_size();
+        let mut aggregate = aggregator.identity();
+        for i in 0..size {
+            // 下标为i的子树中的所有key满足 key(i) <= subtree(value(i)) < key(i+1)
+            let child_lower = if i == 0 { lower } else { page.borrow().key_at(i) as i64 };
+            let child_upper = if i + 1 < size { page.borrow().key_at(i + 1) as i64 } else { upper };
+            if child_upper <= start || end <= child_lower {
+                continue;
+            }
+            let child_page = Self::child_page_at(page, i)?;
+            let covered = start <= child_lower && child_upper <= end;
+            let child_aggregate = if covered && !dirty_pages.contains(&child_page.borrow().get_page_id()) {
+                page.borrow().get_aggregate_at(i)
+            } else {
+                Self::aggregate_page(aggregator, dirty_pages, &child_page, child_lower, child_upper, start, end)?
+            };
+            aggregate = aggregator.combine(aggregate, child_aggregate);
+        }
+        Ok(aggregate)
+    }
+}
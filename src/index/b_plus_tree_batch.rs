@@ -0,0 +1,199 @@
+//! 批量查找和批量修改
+//!
+//! 先把输入按key排序，向下查找叶子节点时记录该叶子节点的key范围[lower, upper)，之后的key只要还在这个范围内
+//! 就直接使用同一个叶子节点，不再从根节点向下查找。叶子节点需要分裂或者合并时交给try_insert()/try_remove()处理
+
+use crate::index::b_plus_tree::{BPlusTree, BPlusTreeError, SearchPath, TreeCorruption};
+use crate::index::b_plus_tree_trace::TraceOp;
+use crate::page::b_plus_tree_page::{RcPage, SizeT, ValueType};
+
+/// 向下查找得到的叶子节点，以及父节点中的分隔key给出的该叶子节点的key范围[lower, upper)
+struct LeafCursor {
+    leaf_page: RcPage,
+    lower: Option<i32>,
+    upper: Option<i32>,
+    path: SearchPath, // 向下查找时经过的内部节点，离开叶子节点时沿着它更新count和聚合值
+    count_delta: isize // 叶子节点中已经插入或删除的key的个数
+}
+
+impl LeafCursor {
+    fn covers(&self, key: i32) -> bool {
+        self.lower.is_none_or(|lower| lower <= key) && self.upper.is_none_or(|upper| key < upper)
+    }
+}
+
+impl BPlusTree {
+    /// 按keys的顺序返回每个key的value
+    pub fn get_many(&self, keys: &[i32]) -> Vec<Option<i32>> {
+        let result = self.lookup_many(keys);
+        self.expect_ok(result)
+    }
+
+    /// 插入所有的键值对，返回插入的个数，与insert()一样不覆盖已经存在的key，同一个key出现多次时只插入第一次出现的value
+    pub fn insert_batch(&mut self, entries: impl IntoIterator<Item = (i32, i32)>) -> SizeT {
+        let result = self.insert_sorted(entries);
+        self.expect_ok(result)
+    }
+
+    /// 删除所有的key，返回实际删除的个数
+    pub fn remove_batch(&mut self, keys: impl IntoIterator<Item = i32>) -> SizeT {
+        let result = self.remove_sorted(keys);
+        self.expect_ok(result)
+    }
+}
+
+impl BPlusTree {
+    fn lookup_many(&self, keys: &[i32]) -> Result<Vec<Option<i32>>, BPlusTreeError> {
+        let mut order: Vec<usize> = (0..keys.len()).collect();
+        order.sort_by_key(|index| keys[*index]);
+
+        let mut values = vec![None; keys.len()];
+        let mut cursor: Option<LeafCursor> = None;
+        for index in order {
+            let key = keys[index];
+            self.trace_begin(TraceOp::Get { key, value: None });
+            let value = self.lookup_with_cursor(&mut cursor, key);
+            self.trace_end(value.as_ref().ok().map(|value| TraceOp::Get { key, value: *value }));
+            values[index] = value?;
+        }
+        Ok(values)
+    }
+
+    fn lookup_with_cursor(&self, cursor: &mut Option<LeafCursor>, key: i32) -> Result<Option<i32>, BPlusTreeError> {
+        if !cursor.as_ref().is_some_and(|cursor| cursor.covers(key)) {
+            *cursor = self.find_leaf_cursor(key)?;
+        }
+        match cursor {
+            None => Ok(None),
+            Some(cursor) => {
+                let value = cursor.leaf_page.borrow().lookup(key);
+                match value {
+                    ValueType::Value(value) => Ok(value),
+                    ValueType::Page(_) => Err(TreeCorruption::InvalidPage { page_id: cursor.leaf_page.borrow().get_page_id() }.into())
+                }
+            }
+        }
+    }
+
+    fn insert_sorted(&mut self, entries: impl IntoIterator<Item = (i32, i32)>) -> Result<SizeT, BPlusTreeError> {
+        // 稳定排序，重复的key保留第一次出现的顺序
+        let mut entries: Vec<(i32, i32)> = entries.into_iter().collect();
+        entries.sort_by_key(|(key, _)| *key);
+        self.rebuild_dirty_aggregates()?;
+
+        let mut inserted_count = 0;
+        let mut cursor: Option<LeafCursor> = None;
+        for (key, value) in entries {
+            if !cursor.as_ref().is_some_and(|cursor| cursor.covers(key)) {
+                self.flush_leaf_cursor(cursor.take())?;
+                cursor = self.find_leaf_cursor(key)?;
+            }
+
+            let inserted = match &mut cursor {
+                // 插入之后叶子节点达到leaf_max_size时需要分裂
+                Some(cursor) if cursor.leaf_page.borrow().get_size() + 1 < self.get_leaf_max_size() => {
+                    self.trace_begin(TraceOp::Insert { key, value, inserted: false });
+                    let old_size = cursor.leaf_page.borrow().get_size();
+                    let new_size = cursor.leaf_page.borrow_mut().insert(key, value);
+                    let inserted = new_size != old_size;
+                    if inserted {
+                        self.set_len(self.len() + 1);
+                        cursor.count_delta += 1;
+                    }
+                    self.trace_end(Some(TraceOp::Insert { key, value, inserted }));
+                    inserted
+                }
+                _ => {
+                    self.flush_leaf_cursor(cursor.take())?;
+                    self.try_insert(key, value)?
+                }
+            };
+            if inserted {
+                inserted_count += 1;
+            }
+        }
+        self.flush_leaf_cursor(cursor)?;
+        self.check_invariants()?;
+        Ok(inserted_count)
+    }
+
+    fn remove_sorted(&mut self, keys: impl IntoIterator<Item = i32>) -> Result<SizeT, BPlusTreeError> {
+        let mut keys: Vec<i32> = keys.into_iter().collect();
+        keys.sort_unstable();
+        keys.dedup();
+        self.rebuild_dirty_aggregates()?;
+
+        let mut removed_count = 0;
+        let mut cursor: Option<LeafCursor> = None;
+        for key in keys {
+            if !cursor.as_ref().is_some_and(|cursor| cursor.covers(key)) {
+                self.flush_leaf_cursor(cursor.take())?;
+                cursor = self.find_leaf_cursor(key)?;
+            }
+
+            let removed = match &mut cursor {
+                // 删除之后叶子节点小于get_min_size()时需要合并或者重新分配
+                Some(cursor) if cursor.leaf_page.borrow().get_size() > cursor.leaf_page.borrow().get_min_size() => {
+                    self.trace_begin(TraceOp::Remove { key, removed: false });
+                    let old_size = cursor.leaf_page.borrow().get_size();
+                    let new_size = cursor.leaf_page.borrow_mut().remove_and_delete_record(key);
+                    let removed = new_size != old_size;
+                    if removed {
+                        self.set_len(self.len() - 1);
+                        cursor.count_delta -= 1;
+                    }
+                    self.trace_end(Some(TraceOp::Remove { key, removed }));
+                    removed
+                }
+                _ => {
+                    self.flush_leaf_cursor(cursor.take())?;
+                    self.try_remove(key)?
+                }
+            };
+            if removed {
+                removed_count += 1;
+            }
+        }
+        self.flush_leaf_cursor(cursor)?;
+        self.check_invariants()?;
+        Ok(removed_count)
+    }
+
+    /// 离开一个叶子节点时才更新它到根节点路径上的count和聚合值
+    fn flush_leaf_cursor(&mut self, cursor: Option<LeafCursor>) -> Result<(), BPlusTreeError> {
+        match cursor {
+            None => Ok(()),
+            Some(cursor) => self.refresh_path(&cursor.path, cursor.count_delta)
+        }
+    }
+
+    fn find_leaf_cursor(&self, key: i32) -> Result<Option<LeafCursor>, BPlusTreeError> {
+        let mut cur_page = match self.get_root_page() {
+            None => {
+                return Ok(None);
+            }
+            Some(root_page) => {
+                root_page
+            }
+        };
+
+        let mut lower = None;
+        let mut upper = None;
+        let mut path = SearchPath::new();
+        while cur_page.borrow().is_internal_page() {
+            let (index, _) = cur_page.borrow().lookup_traced(key, |_, _| {});
+            let index = index.ok_or_else(|| TreeCorruption::InvalidPage { page_id: cur_page.borrow().get_page_id() })?;
+            // 下标为index的子树中的所有key满足 key(index) <= subtree(value(index)) < key(index+1)
+            if index > 0 {
+                lower = Some(cur_page.borrow().key_at(index));
+            }
+            if index + 1 < cur_page.borrow().get_size() {
+                upper = Some(cur_page.borrow().key_at(index + 1));
+            }
+            let child_page = Self::child_page_at(&cur_page, index)?;
+            path.push((cur_page, index));
+            cur_page = child_page;
+        }
+        Ok(Some(LeafCursor { leaf_page: cur_page, lower, upper, path, count_delta: 0 }))
+    }
+}
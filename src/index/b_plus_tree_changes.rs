@@ -0,0 +1,162 @@
+//! 变更订阅
+//!
+//! 打开变更日志之后，每次插入、修改和删除key都会产生一条带序号的变更事件，保存在内存中最近capacity条的
+//! 变更日志里。订阅者按key的范围订阅，用poll()按序号顺序取出新的事件；记住最后的序号的订阅者之后可以用
+//! subscribe_from()从该序号继续，只要这些事件还没有被新的事件挤出变更日志。
+//!
+//! 变更日志只保存在内存中，没有写入磁盘，树被释放之后无法再恢复订阅。订阅者落后太多、需要的事件已经被挤出
+//! 变更日志时，poll()和subscribe_from()返回BPlusTreeError::SequenceUnavailable，不会跳过这些事件继续返回，
+//! 订阅者需要重新读取整棵树之后再用subscribe()订阅。同一棵树中的序号一直增长，关闭后重新打开变更日志时也不会重复，
+//! 所以旧的订阅不会读到新日志中无关的事件。
+//!
+//! 变更事件与操作日志来自同一个记录点，所以split_off()、remove_range()等批量操作会产生每个key的事件；
+//! 通过entries_mut()修改的value在下一次操作或者poll()时产生Updated事件
+
+use std::collections::VecDeque;
+use std::ops::{Bound, RangeBounds};
+use crate::index::b_plus_tree::{BPlusTree, BPlusTreeError};
+use crate::index::b_plus_tree_trace::TraceOp;
+use crate::page::b_plus_tree_page::SizeT;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Change {
+    Inserted { value: i32 },
+    Updated { previous: i32, value: i32 },
+    Removed
+}
+
+/// 第一次打开变更日志时sequence从0开始，每条事件加1
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChangeEvent {
+    pub sequence: u64,
+    pub key: i32,
+    pub change: Change
+}
+
+/// 最近capacity条变更事件
+pub(crate) struct ChangeLog {
+    events_: VecDeque<ChangeEvent>,
+    capacity_: SizeT,
+    next_sequence_: u64
+}
+
+impl ChangeLog {
+    /// first_sequence为第一条事件的序号
+    pub(crate) fn new(capacity: SizeT, first_sequence: u64) -> Self {
+        ChangeLog { events_: VecDeque::new(), capacity_: capacity, next_sequence_: first_sequence }
+    }
+
+    /// 把修改了树的操作转换成变更事件，查找和没有修改树的操作被忽略
+    pub(crate) fn record(&mut self, op: TraceOp) {
+        let (key, change) = match op {
+            TraceOp::Insert { key, value, inserted: true } => (key, Change::Inserted { value }),
+            TraceOp::Update { key, value, previous: Some(previous) } => (key, Change::Updated { previous, value }),
+            TraceOp::Remove { key, removed: true } => (key, Change::Removed),
+            _ => {
+                return;
+            }
+        };
+
+        if self.events_.len() == self.capacity_ {
+            self.events_.pop_front();
+        }
+        if self.capacity_ > 0 {
+            self.events_.push_back(ChangeEvent { sequence: self.next_sequence_, key, change });
+        }
+        self.next_sequence_ += 1;
+    }
+
+    /// 变更日志中最早的事件的序号，日志为空时为下一条事件的序号
+    fn get_oldest_sequence(&self) -> u64 {
+        self.events_.front().map_or(self.next_sequence_, |event| event.sequence)
+    }
+
+    pub(crate) fn get_next_sequence(&self) -> u64 {
+        self.next_sequence_
+    }
+}
+
+/// 一个key范围的订阅，记录下一条要读取的事件的序号
+#[derive(Clone, Debug, PartialEq)]
+pub struct Subscription {
+    range_: (Bound<i32>, Bound<i32>),
+    next_sequence_: u64
+}
+
+impl Subscription {
+    /// 下一次poll()从这个序号开始，保存下来之后可以用subscribe_from()恢复订阅
+    pub fn get_next_sequence(&self) -> u64 {
+        self.next_sequence_
+    }
+}
+
+impl BPlusTree {
+    /// 打开变更日志，最多保存最近capacity条事件
+    ///
+    /// 已经打开时清空原来的日志，新的序号跳过原来的日志之后的一个序号，原来的订阅之后返回SequenceUnavailable
+    pub fn enable_change_log(&mut self, capacity: SizeT) {
+        self.flush_value_updates();
+        self.replace_change_log(Some(capacity));
+    }
+
+    /// 关闭变更日志，之后poll()返回BPlusTreeError::ChangeLogDisabled
+    pub fn disable_change_log(&mut self) {
+        self.flush_value_updates();
+        self.replace_change_log(None);
+    }
+
+    /// 下一条变更事件的序号，变更日志没有打开时为下一次打开变更日志之后第一条事件的序号
+    pub fn get_next_sequence(&self) -> u64 {
+        self.flush_value_updates();
+        self.get_change_log().as_ref().map_or(self.get_next_change_sequence(), |change_log| change_log.next_sequence_)
+    }
+
+    /// 变更日志中还保存着的最早的事件的序号，subscribe_from()只能从[get_oldest_sequence(), get_next_sequence()]中的序号恢复，
+    /// 变更日志没有打开时返回None
+    pub fn get_oldest_sequence(&self) -> Option<u64> {
+        self.flush_value_updates();
+        self.get_change_log().as_ref().map(|change_log| change_log.get_oldest_sequence())
+    }
+
+    /// 订阅range中的key从现在开始的变更
+    pub fn subscribe(&self, range: impl RangeBounds<i32>) -> Subscription {
+        Subscription { range_: (range.start_bound().cloned(), range.end_bound().cloned()), next_sequence_: self.get_next_sequence() }
+    }
+
+    /// 从序号sequence开始订阅range中的key的变更，这些事件已经不在变更日志中时返回错误
+    pub fn subscribe_from(&self, range: impl RangeBounds<i32>, sequence: u64) -> Result<Subscription, BPlusTreeError> {
+        self.flush_value_updates();
+        let change_log = self.get_change_log();
+        let change_log = change_log.as_ref().ok_or(BPlusTreeError::ChangeLogDisabled)?;
+        Self::check_sequence(change_log, sequence)?;
+        Ok(Subscription { range_: (range.start_bound().cloned(), range.end_bound().cloned()), next_sequence_: sequence })
+    }
+
+    /// 按序号顺序返回订阅之后新产生的、key在订阅范围内的事件
+    ///
+    /// 订阅者太久没有poll()，还没读取的事件已经被挤出变更日志时返回BPlusTreeError::SequenceUnavailable，
+    /// 订阅保持不变
+    pub fn poll(&self, subscription: &mut Subscription) -> Result<Vec<ChangeEvent>, BPlusTreeError> {
+        self.flush_value_updates();
+        let change_log = self.get_change_log();
+        let change_log = change_log.as_ref().ok_or(BPlusTreeError::ChangeLogDisabled)?;
+        Self::check_sequence(change_log, subscription.next_sequence_)?;
+
+        let events = change_log.events_.iter()
+            .filter(|event| event.sequence >= subscription.next_sequence_ && subscription.range_.contains(&event.key))
+            .copied()
+            .collect();
+        subscription.next_sequence_ = change_log.next_sequence_;
+        Ok(events)
+    }
+}
+
+impl BPlusTree {
+    fn check_sequence(change_log: &ChangeLog, sequence: u64) -> Result<(), BPlusTreeError> {
+        let oldest = change_log.get_oldest_sequence();
+        if sequence < oldest || sequence > change_log.next_sequence_ {
+            return Err(BPlusTreeError::SequenceUnavailable { sequence, oldest, next: change_log.next_sequence_ });
+        }
+        Ok(())
+    }
+}
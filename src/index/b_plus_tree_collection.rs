@@ -0,0 +1,120 @@
+//! 与BTreeMap<i32, i32>一致的标准库trait，方便直接替换BTreeMap
+//!
+//! 与BTreeMap不同的是，insert()遇到已经存在的key时不覆盖原来的value，而Extend和FromIterator
+//! 与BTreeMap一样用后出现的value覆盖之前的value
+
+use std::hash::{Hash, Hasher};
+use std::ops::Index;
+use crate::index::b_plus_tree::{BPlusTree, Operation};
+use crate::iterator::b_plus_tree_iterator::{BPlusTreeEntries, BPlusTreeEntriesMut, BPlusTreeIntoIter};
+use crate::page::b_plus_tree_page::SizeT;
+
+pub const DEFAULT_INTERNAL_MAX_SIZE: SizeT = 128;
+pub const DEFAULT_LEAF_MAX_SIZE: SizeT = 128;
+
+impl Default for BPlusTree {
+    fn default() -> Self {
+        BPlusTree::new(String::new(), DEFAULT_INTERNAL_MAX_SIZE, DEFAULT_LEAF_MAX_SIZE)
+    }
+}
+
+/// 按key从小到大重新插入所有的键值对，新树的形状以及split/merge/redistribute的统计次数都与原来的树无关，
+/// 正在进行的录制和操作日志也不会被复制，聚合函数和合并函数与原来的树共享
+impl Clone for BPlusTree {
+    fn clone(&self) -> Self {
+        let mut tree = BPlusTree::new(self.get_index_name().to_string(), self.get_internal_max_size(), self.get_leaf_max_size());
+        let result = tree.replace_aggregator(self.get_aggregator().cloned());
+        tree.expect_ok(result);
+        tree.replace_merge_operator(self.get_merge_operator().cloned());
+        tree.extend(self);
+        tree
+    }
+}
+
+/// 只比较键值对，不比较名字和节点大小
+impl PartialEq for BPlusTree {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.entries().eq(other.entries())
+    }
+}
+
+impl Eq for BPlusTree {}
+
+impl Hash for BPlusTree {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.len().hash(state);
+        for entry in self {
+            entry.hash(state);
+        }
+    }
+}
+
+impl Index<&i32> for BPlusTree {
+    type Output = i32;
+
+    /// key不存在时panic
+    fn index(&self, key: &i32) -> &i32 {
+        self.get(key).expect("no entry found for key")
+    }
+}
+
+impl FromIterator<(i32, i32)> for BPlusTree {
+    fn from_iter<T: IntoIterator<Item = (i32, i32)>>(iter: T) -> Self {
+        let mut tree = BPlusTree::default();
+        tree.extend(iter);
+        tree
+    }
+}
+
+impl Extend<(i32, i32)> for BPlusTree {
+    fn extend<T: IntoIterator<Item = (i32, i32)>>(&mut self, iter: T) {
+        for (key, value) in iter {
+            self.insert_or_update(key, value);
+        }
+    }
+}
+
+impl<'a> Extend<(&'a i32, &'a i32)> for BPlusTree {
+    fn extend<T: IntoIterator<Item = (&'a i32, &'a i32)>>(&mut self, iter: T) {
+        self.extend(iter.into_iter().map(|(key, value)| (*key, *value)));
+    }
+}
+
+/// 按key从小到大消耗所有的键值对，每遍历完一个叶子节点就释放其中的元素
+impl IntoIterator for BPlusTree {
+    type Item = (i32, i32);
+    type IntoIter = BPlusTreeIntoIter;
+
+    fn into_iter(self) -> Self::IntoIter {
+        let left_most_leaf_page = self.expect_ok(self.find_leaf_page(0, Operation::FIND, true, false));
+        BPlusTreeIntoIter::new(left_most_leaf_page, self.len())
+    }
+}
+
+impl<'a> IntoIterator for &'a BPlusTree {
+    type Item = (&'a i32, &'a i32);
+    type IntoIter = BPlusTreeEntries<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries()
+    }
+}
+
+impl<'a> IntoIterator for &'a mut BPlusTree {
+    type Item = (&'a i32, &'a mut i32);
+    type IntoIter = BPlusTreeEntriesMut<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries_mut()
+    }
+}
+
+impl BPlusTree {
+    /// 与BTreeMap::insert()一样，key已经存在时覆盖原来的value
+    fn insert_or_update(&mut self, key: i32, value: i32) {
+        if !self.insert(key, value) {
+            let result = self.update_value(key, value);
+            self.expect_ok(result);
+        }
+    }
+}
@@ -0,0 +1,38 @@
+//! 条件写入
+//!
+//! 比较和写入在同一个&mut self借用中完成，中间不会有其他修改，所以两者是原子的。
+//! 只向下查找一次key所在的叶子节点，直接在叶子节点中比较和写入
+
+use crate::index::b_plus_tree::{BPlusTree, BPlusTreeError};
+
+impl BPlusTree {
+    /// key当前的value等于expected时把它改为new，None表示key不存在或者删除key
+    ///
+    /// 与AtomicI32::compare_exchange()一样，成功时返回Ok(原来的value)，失败时返回Err(当前的value)
+    pub fn compare_and_swap(&mut self, key: i32, expected: Option<i32>, new: Option<i32>) -> Result<Option<i32>, Option<i32>> {
+        let result = self.try_compare_and_swap(key, expected, new);
+        self.expect_ok(result)
+    }
+
+    /// key不存在时插入并返回Ok(())，否则不修改并返回Err(当前的value)
+    pub fn insert_if_absent(&mut self, key: i32, value: i32) -> Result<(), i32> {
+        match self.compare_and_swap(key, None, Some(value)) {
+            Ok(_) => Ok(()),
+            Err(current) => Err(current.expect("compare_and_swap() only fails when the key exists"))
+        }
+    }
+}
+
+impl BPlusTree {
+    fn try_compare_and_swap(&mut self, key: i32, expected: Option<i32>, new: Option<i32>)
+                            -> Result<Result<Option<i32>, Option<i32>>, BPlusTreeError> {
+        self.rebuild_dirty_aggregates()?;
+        let leaf_path = self.find_leaf_path(key)?;
+        let current = Self::leaf_value(&leaf_path, key)?;
+        if current != expected {
+            return Ok(Err(current));
+        }
+        self.write_leaf_entry(leaf_path, key, current, new)?;
+        Ok(Ok(current))
+    }
+}
@@ -0,0 +1,98 @@
+use std::fmt::{Display, Formatter};
+use crate::index::b_plus_tree::BPlusTree;
+use crate::index::b_plus_tree_render::DotOptions;
+use crate::page::b_plus_tree_page::{BPlusTreePageType, ValueType};
+
+/// 查找路径上的一个节点
+#[derive(Clone, Debug, PartialEq)]
+pub struct LookupStep {
+    pub page_id: usize,
+    pub page_type: BPlusTreePageType,
+    /// 二分查找时依次比较过的关键字
+    pub compared_keys: Vec<i32>,
+    /// 内部节点为lookup选中的孩子下标，叶子节点为key所在的下标，没有找到时为None
+    pub chosen_index: Option<usize>,
+    /// 内部节点选中的孩子节点，叶子节点为None
+    pub child_page_id: Option<usize>
+}
+
+/// BPlusTree::explain_get()的返回值，steps按从根节点到叶子节点的顺序排列
+#[derive(Clone, Debug, PartialEq)]
+pub struct LookupExplain {
+    pub key: i32,
+    pub steps: Vec<LookupStep>,
+    pub result: Option<i32>
+}
+
+impl LookupExplain {
+    pub fn page_ids(&self) -> Vec<usize> {
+        self.steps.iter().map(|step| step.page_id).collect()
+    }
+}
+
+impl Display for LookupExplain {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "get {}", self.key)?;
+        for (level, step) in self.steps.iter().enumerate() {
+            let page_type = if step.page_type == BPlusTreePageType::LeafPage { "leaf" } else { "internal" };
+            write!(f, "  level {}: {} page {} compared {:?}", level, page_type, step.page_id, step.compared_keys)?;
+            match (step.chosen_index, step.child_page_id) {
+                (Some(index), Some(child_page_id)) => writeln!(f, " -> child {} (page {})", index, child_page_id)?,
+                (Some(index), None) => writeln!(f, " -> found at {}", index)?,
+                (None, _) => writeln!(f, " -> not found")?
+            }
+        }
+        match self.result {
+            Some(value) => writeln!(f, "result: {}", value),
+            None => writeln!(f, "result: None")
+        }
+    }
+}
+
+impl BPlusTree {
+    /// 与get_value()沿着同样的路径查找key，并记录经过的每个节点比较过的关键字和选中的孩子
+    pub fn explain_get(&self, key: i32) -> LookupExplain {
+        let mut explain = LookupExplain { key, steps: Vec::new(), result: None };
+        let mut cur_page = self.get_root_page();
+
+        while let Some(page) = cur_page {
+            let mut compared_keys = Vec::new();
+            let (chosen_index, value) = page.borrow().lookup_traced(key, |_, compared_key| compared_keys.push(compared_key));
+
+            let mut step = LookupStep {
+                page_id: page.borrow().get_page_id(),
+                page_type: page.borrow().get_page_type(),
+                compared_keys,
+                chosen_index,
+                child_page_id: None
+            };
+
+            cur_page = match value {
+                ValueType::Page(child_page) => {
+                    step.child_page_id = child_page.as_ref().map(|child_page| child_page.borrow().get_page_id());
+                    child_page
+                }
+                ValueType::Value(value) => {
+                    explain.result = value;
+                    None
+                }
+            };
+            explain.steps.push(step);
+        }
+
+        explain
+    }
+
+    /// 与draw()相同，但是高亮显示查找key时经过的节点
+    pub fn draw_lookup(&self, key: i32) {
+        println!("{}", self.to_dot_lookup(key));
+    }
+
+    pub fn to_dot_lookup(&self, key: i32) -> String {
+        self.to_dot_with_options(&DotOptions {
+            title: Some(format!("get {}", key)),
+            highlighted_page_ids: self.explain_get(key).page_ids(),
+            ..Default::default()
+        })
+    }
+}
@@ -0,0 +1,71 @@
+//! 读-改-写合并
+//!
+//! 设置合并函数之后，merge_value(key, operand)在一次向下查找中找到key所在的叶子节点，
+//! 用合并函数根据原来的value和operand直接在叶子节点中算出新的value，不需要先get_value()再insert()。
+//! 操作日志中记录的是合并后的结果，回放时不需要合并函数
+
+use std::rc::Rc;
+use crate::index::b_plus_tree::{BPlusTree, BPlusTreeError};
+
+/// 合并函数，existing为None表示key还不存在
+pub trait MergeOperator {
+    fn merge(&self, key: i32, existing: Option<i32>, operand: i32) -> i32;
+}
+
+/// 计数器，key不存在时从0开始累加，溢出时回绕
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Increment;
+
+impl MergeOperator for Increment {
+    fn merge(&self, _key: i32, existing: Option<i32>, operand: i32) -> i32 {
+        existing.unwrap_or(0).wrapping_add(operand)
+    }
+}
+
+/// 保留最大值
+#[derive(Clone, Copy, Debug, Default)]
+pub struct KeepMax;
+
+impl MergeOperator for KeepMax {
+    fn merge(&self, _key: i32, existing: Option<i32>, operand: i32) -> i32 {
+        existing.map_or(operand, |existing| existing.max(operand))
+    }
+}
+
+/// 保留最小值
+#[derive(Clone, Copy, Debug, Default)]
+pub struct KeepMin;
+
+impl MergeOperator for KeepMin {
+    fn merge(&self, _key: i32, existing: Option<i32>, operand: i32) -> i32 {
+        existing.map_or(operand, |existing| existing.min(operand))
+    }
+}
+
+impl BPlusTree {
+    /// 设置合并函数，替换之前的合并函数
+    pub fn set_merge_operator(&mut self, merge_operator: impl MergeOperator + 'static) {
+        self.replace_merge_operator(Some(Rc::new(merge_operator)));
+    }
+
+    pub fn remove_merge_operator(&mut self) {
+        self.replace_merge_operator(None);
+    }
+
+    /// 与try_merge_value()相同，没有设置合并函数或者树的结构损坏时panic
+    pub fn merge_value(&mut self, key: i32, operand: i32) -> i32 {
+        let result = self.try_merge_value(key, operand);
+        self.expect_ok(result)
+    }
+
+    /// 用合并函数把operand合并到key原来的value中，key不存在时插入合并的结果，返回合并后的value
+    pub fn try_merge_value(&mut self, key: i32, operand: i32) -> Result<i32, BPlusTreeError> {
+        let merge_operator = self.get_merge_operator().cloned().ok_or(BPlusTreeError::NoMergeOperator)?;
+        self.rebuild_dirty_aggregates()?;
+        let leaf_path = self.find_leaf_path(key)?;
+        let previous = Self::leaf_value(&leaf_path, key)?;
+        let value = merge_operator.merge(key, previous, operand);
+        self.write_leaf_entry(leaf_path, key, previous, Some(value))?;
+        Ok(value)
+    }
+}
@@ -0,0 +1,178 @@
+//! 把随机生成的操作序列同时作用在BPlusTree和std::collections::BTreeMap上，
+//! 每一步都比较两者的结果并调用validate()检查树的结构。
+//!
+//! 发现不一致时，会把操作序列收缩成仍然能复现问题的最小序列再报告出来。
+
+use std::collections::BTreeMap;
+use std::panic::{self, AssertUnwindSafe};
+use crate::index::b_plus_tree::BPlusTree;
+use crate::page::b_plus_tree_page::SizeT;
+
+const MAX_SIZES: [SizeT; 4] = [3, 4, 5, 128];
+const KEY_SPACES: [i32; 3] = [16, 64, 512];
+const CASES_PER_SIZE: u64 = 6;
+const OPS_PER_CASE: usize = 400;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Op {
+    Insert(i32, i32),
+    Remove(i32),
+    Get(i32),
+    Range(i32, i32),
+    Iter
+}
+
+/// xorshift64*，保证每个seed生成的操作序列都是确定的
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+}
+
+fn generate(rng: &mut Rng, len: usize, key_space: i32) -> Vec<Op> {
+    let mut ops = Vec::with_capacity(len);
+    for step in 0..len {
+        let key = rng.below(key_space as u64) as i32;
+        let op = match rng.below(20) {
+            0..=7 => Op::Insert(key, step as i32),
+            8..=12 => Op::Remove(key),
+            13..=16 => Op::Get(key),
+            17..=18 => Op::Range(key, key + rng.below(key_space as u64 / 4 + 1) as i32),
+            _ => Op::Iter
+        };
+        ops.push(op);
+    }
+    ops
+}
+
+fn check(ops: &[Op], internal_max_size: SizeT, leaf_max_size: SizeT) -> Result<(), String> {
+    let mut tree = BPlusTree::new(String::from("model"), internal_max_size, leaf_max_size);
+    let mut model = BTreeMap::new();
+
+    for (step, op) in ops.iter().enumerate() {
+        let (actual, expected) = match *op {
+            Op::Insert(key, value) => {
+                // BPlusTree不会覆盖已经存在的key
+                let expected = !model.contains_key(&key);
+                if expected {
+                    model.insert(key, value);
+                }
+                (format!("{:?}", tree.insert(key, value)), format!("{:?}", expected))
+            }
+            Op::Remove(key) => {
+                tree.remove(key);
+                model.remove(&key);
+                (format!("{:?}", tree.get_value(key)), format!("{:?}", None::<i32>))
+            }
+            Op::Get(key) => {
+                (format!("{:?}", tree.get_value(key)), format!("{:?}", model.get(&key)))
+            }
+            Op::Range(start_key, end_key) => {
+                let actual: Vec<i32> = tree.range(start_key, end_key).collect();
+                let expected: Vec<i32> = model.range(start_key..end_key).map(|(_, v)| *v).collect();
+                (format!("{:?}", actual), format!("{:?}", expected))
+            }
+            Op::Iter => {
+                let actual: Vec<(&i32, &i32)> = tree.entries().collect();
+                let expected: Vec<(&i32, &i32)> = model.iter().collect();
+                (format!("{} {:?} {:?}", tree.len(), tree.iter().collect::<Vec<i32>>(), actual),
+                 format!("{} {:?} {:?}", model.len(), model.values().collect::<Vec<&i32>>(), expected))
+            }
+        };
+
+        if actual != expected {
+            return Err(format!("step {}: {:?} returned {}, BTreeMap returned {}", step, op, actual, expected));
+        }
+        if let Err(corruption) = tree.validate() {
+            return Err(format!("step {}: {:?} corrupted the tree: {}", step, op, corruption));
+        }
+    }
+
+    if tree.is_empty() != model.is_empty() {
+        return Err(format!("tree is_empty() is {}, BTreeMap is_empty() is {}", tree.is_empty(), model.is_empty()));
+    }
+    Ok(())
+}
+
+/// 和check()一样，但是把树的panic也当作一次失败
+fn run(ops: &[Op], internal_max_size: SizeT, leaf_max_size: SizeT) -> Result<(), String> {
+    match panic::catch_unwind(AssertUnwindSafe(|| check(ops, internal_max_size, leaf_max_size))) {
+        Ok(result) => result,
+        Err(payload) => {
+            let message = payload.downcast_ref::<&str>().map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_default();
+            Err(format!("panicked: {}", message))
+        }
+    }
+}
+
+/// 不断删除连续的一段操作，只要剩下的序列仍然失败就保留删除结果，
+/// 直到删除任何一个操作都不能再复现失败为止
+fn shrink<F: Fn(&[Op]) -> bool>(mut ops: Vec<Op>, fails: F) -> Vec<Op> {
+    let mut chunk = ops.len() / 2;
+    while chunk > 0 {
+        let mut removed = false;
+        let mut start = 0;
+        while start < ops.len() {
+            let end = usize::min(start + chunk, ops.len());
+            let mut candidate = ops[..start].to_vec();
+            candidate.extend_from_slice(&ops[end..]);
+            if fails(&candidate) {
+                ops = candidate;
+                removed = true;
+            } else {
+                start += chunk;
+            }
+        }
+        if !removed {
+            chunk /= 2;
+        }
+    }
+    ops
+}
+
+#[test]
+fn b_plus_tree_model_test() {
+    for internal_max_size in MAX_SIZES {
+        for leaf_max_size in MAX_SIZES {
+            for seed in 0..CASES_PER_SIZE {
+                let key_space = KEY_SPACES[seed as usize % KEY_SPACES.len()];
+                let ops = generate(&mut Rng::new(seed), OPS_PER_CASE, key_space);
+
+                if run(&ops, internal_max_size, leaf_max_size).is_err() {
+                    let minimal = shrink(ops, |ops| run(ops, internal_max_size, leaf_max_size).is_err());
+                    panic!("internal_max_size={} leaf_max_size={} seed={}: {}\nminimal reproduction: {:?}",
+                           internal_max_size, leaf_max_size, seed,
+                           run(&minimal, internal_max_size, leaf_max_size).unwrap_err(), minimal);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn b_plus_tree_model_shrink_test() {
+    let ops = generate(&mut Rng::new(7), 200, 8);
+    let fails = |ops: &[Op]| {
+        ops.contains(&Op::Get(3)) && ops.contains(&Op::Remove(5))
+    };
+    assert!(fails(&ops));
+
+    let minimal = shrink(ops, fails);
+    assert_eq!(2, minimal.len());
+    assert!(fails(&minimal));
+}
@@ -0,0 +1,143 @@
+use crate::index::b_plus_tree::{BPlusTree, BPlusTreeError, Operation, TreeCorruption};
+use crate::page::b_plus_tree_page::{Page, RcPage};
+
+/// 有序map的导航查询，都只需要从根节点向下查找一次，时间复杂度为O(log n)
+impl BPlusTree {
+    /// 最小的key和它的value
+    pub fn first_key_value(&self) -> Option<(&i32, &i32)> {
+        let result = self.find_leaf_page(0, Operation::FIND, true, false).and_then(|leaf_page| match leaf_page {
+            None => Ok(None),
+            Some(leaf_page) => self.leaf_entry_at(&leaf_page, 0)
+        });
+        self.expect_ok(result)
+    }
+
+    /// 最大的key和它的value
+    pub fn last_key_value(&self) -> Option<(&i32, &i32)> {
+        let result = self.find_leaf_page(0, Operation::FIND, false, true).and_then(|leaf_page| match leaf_page {
+            None => Ok(None),
+            Some(leaf_page) => self.last_leaf_entry(&leaf_page)
+        });
+        self.expect_ok(result)
+    }
+
+    /// 删除并返回最小的key和它的value
+    pub fn pop_first(&mut self) -> Option<(i32, i32)> {
+        let (key, value) = self.first_key_value().map(|(key, value)| (*key, *value))?;
+        self.remove(key);
+        Some((key, value))
+    }
+
+    /// 删除并返回最大的key和它的value
+    pub fn pop_last(&mut self) -> Option<(i32, i32)> {
+        let (key, value) = self.last_key_value().map(|(key, value)| (*key, *value))?;
+        self.remove(key);
+        Some((key, value))
+    }
+
+    /// 小于等于key的最大的key
+    pub fn floor(&self, key: i32) -> Option<(&i32, &i32)> {
+        let result = self.find_floor(key);
+        self.expect_ok(result)
+    }
+
+    /// 大于等于key的最小的key
+    pub fn ceiling(&self, key: i32) -> Option<(&i32, &i32)> {
+        let result = self.find_ceiling(key);
+        self.expect_ok(result)
+    }
+
+    /// 严格小于key的最大的key
+    pub fn predecessor(&self, key: i32) -> Option<(&i32, &i32)> {
+        self.floor(key.checked_sub(1)?)
+    }
+
+    /// 严格大于key的最小的key
+    pub fn successor(&self, key: i32) -> Option<(&i32, &i32)> {
+        self.ceiling(key.checked_add(1)?)
+    }
+}
+
+impl BPlusTree {
+    fn last_leaf_entry(&self, leaf_page: &RcPage) -> Result<Option<(&i32, &i32)>, BPlusTreeError> {
+        let size = leaf_page.borrow().get_size();
+        if size == 0 {
+            return Ok(None);
+        }
+        self.leaf_entry_at(leaf_page, size - 1)
+    }
+
+    fn find_ceiling(&self, key: i32) -> Result<Option<(&i32, &i32)>, BPlusTreeError> {
+        let leaf_page = match self.find_leaf_page(key, Operation::FIND, false, false)? {
+            None => {
+                return Ok(None);
+            }
+            Some(leaf_page) => {
+                leaf_page
+            }
+        };
+
+        // 当前叶子节点中所有的key都小于key时，答案是下一个叶子节点的第一个key
+        let index = leaf_page.borrow().key_index(key);
+        if index < leaf_page.borrow().get_size() {
+            return self.leaf_entry_at(&leaf_page, index);
+        }
+        let next_page = leaf_page.borrow().get_next_page();
+        match next_page {
+            None => Ok(None),
+            Some(next_page) => self.leaf_entry_at(&next_page, 0)
+        }
+    }
+
+    fn find_floor(&self, key: i32) -> Result<Option<(&i32, &i32)>, BPlusTreeError> {
+        let mut cur_page = match self.get_root_page() {
+            None => {
+                return Ok(None);
+            }
+            Some(root_page) => {
+                root_page
+            }
+        };
+
+        // 叶子节点没有指向前一个叶子节点的指针，所以向下查找时记录离路径最近的左边子树，
+        // 这棵子树中所有的key都小于路径上叶子节点中的key
+        let mut left_neighbor: Page = None;
+        while cur_page.borrow().is_internal_page() {
+            let (index, _) = cur_page.borrow().lookup_traced(key, |_, _| {});
+            let index = match index {
+                None => {
+                    return Err(TreeCorruption::InvalidPage { page_id: cur_page.borrow().get_page_id() }.into());
+                }
+                Some(index) => {
+                    index
+                }
+            };
+            if index > 0 {
+                left_neighbor = Some(Self::child_page_at(&cur_page, index - 1)?);
+            }
+            cur_page = Self::child_page_at(&cur_page, index)?;
+        }
+        let leaf_page = cur_page;
+        if !leaf_page.borrow().is_leaf_page() {
+            return Err(TreeCorruption::InvalidPage { page_id: leaf_page.borrow().get_page_id() }.into());
+        }
+
+        let index = leaf_page.borrow().key_index(key);
+        if let Some((found_key, value)) = self.leaf_entry_at(&leaf_page, index)? {
+            if *found_key == key {
+                return Ok(Some((found_key, value)));
+            }
+        }
+        if index > 0 {
+            return self.leaf_entry_at(&leaf_page, index - 1);
+        }
+
+        match left_neighbor {
+            None => Ok(None),
+            Some(left_neighbor) => {
+                let leaf_page = Self::find_leaf_page_from(left_neighbor, key, Operation::FIND, false, true)?;
+                self.last_leaf_entry(&leaf_page)
+            }
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use std::ops::{Bound, RangeBounds};
+use crate::index::b_plus_tree::{BPlusTree, BPlusTreeError, TreeCorruption};
+use crate::page::b_plus_tree_page::SizeT;
+
+/// 顺序统计查询：内部节点记录了每个孩子子树中key的个数，所以向下查找一次就能求出排名，时间复杂度为O(log n)
+impl BPlusTree {
+    /// 树中小于key的key的个数，也就是key在所有key中从0开始的排名
+    pub fn rank(&self, key: i32) -> SizeT {
+        let result = self.count_less(key, false);
+        self.expect_ok(result)
+    }
+
+    /// 从0开始第index小的key和它的value，index >= len()时返回None
+    pub fn select(&self, index: SizeT) -> Option<(&i32, &i32)> {
+        let result = self.find_by_index(index);
+        self.expect_ok(result)
+    }
+
+    /// range中key的个数
+    pub fn count_range(&self, range: impl RangeBounds<i32>) -> SizeT {
+        let result = self.count_in_range(range);
+        self.expect_ok(result)
+    }
+}
+
+impl BPlusTree {
+    fn count_in_range(&self, range: impl RangeBounds<i32>) -> Result<SizeT, BPlusTreeError> {
+        let start = match range.start_bound() {
+            Bound::Included(start) => self.count_less(*start, false)?,
+            Bound::Excluded(start) => self.count_less(*start, true)?,
+            Bound::Unbounded => 0
+        };
+        let end = match range.end_bound() {
+            Bound::Included(end) => self.count_less(*end, true)?,
+            Bound::Excluded(end) => self.count_less(*end, false)?,
+            Bound::Unbounded => self.len()
+        };
+        Ok(end.saturating_sub(start))
+    }
+
+    /// 小于key（inclusive为true时为小于等于key）的key的个数
+    fn count_less(&self, key: i32, inclusive: bool) -> Result<SizeT, BPlusTreeError> {
+        let mut cur_page = match self.get_root_page() {
+            None => {
+                return Ok(0);
+            }
+            Some(root_page) => {
+                root_page
+            }
+        };
+
+        // 累加路径左边所有孩子的count
+        let mut count = 0;
+        while cur_page.borrow().is_internal_page() {
+            let (index, _) = cur_page.borrow().lookup_traced(key, |_, _| {});
+            let index = match index {
+                None => {
+                    return Err(TreeCorruption::InvalidPage { page_id: cur_page.borrow().get_page_id() }.into());
+                }
+                Some(index) => {
+                    index
+                }
+            };
+            count += (0..index).map(|i| cur_page.borrow().get_count_at(i)).sum::<SizeT>();
+            cur_page = Self::child_page_at(&cur_page, index)?;
+        }
+
+        let index = cur_page.borrow().key_index(key);
+        count += index;
+        if inclusive && index < cur_page.borrow().get_size() && cur_page.borrow().key_at(index) == key {
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    fn find_by_index(&self, mut index: SizeT) -> Result<Option<(&i32, &i32)>, BPlusTreeError> {
+        if index >= self.len() {
+            return Ok(None);
+        }
+        let mut cur_page = match self.get_root_page() {
+            None => {
+                return Ok(None);
+            }
+            Some(root_page) => {
+                root_page
+            }
+        };
+
+        // 跳过count之和不超过index的孩子
+        while cur_page.borrow().is_internal_page() {
+            let size = cur_page.borrow().get_size();
+            let mut child_index = 0;
+            while child_index < size && index >= cur_page.borrow().get_count_at(child_index) {
+                index -= cur_page.borrow().get_count_at(child_index);
+                child_index += 1;
+            }
+            if child_index == size {
+                return Err(TreeCorruption::InvalidPage { page_id: cur_page.borrow().get_page_id() }.into());
+            }
+            cur_page = Self::child_page_at(&cur_page, child_index)?;
+        }
+
+        self.leaf_entry_at(&cur_page, index)
+    }
+}
@@ -0,0 +1,119 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use crate::index::b_plus_tree::BPlusTree;
+use crate::index::b_plus_tree_render::DotOptions;
+
+/// 录制模式下会产生一帧的结构变化
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StructuralEvent {
+    /// 叶子节点分裂，新的叶子节点已经插入父节点
+    LeafSplit,
+    /// 内部节点分裂，新的内部节点已经插入父节点
+    InsertIntoParent,
+    /// 两个节点合并，父节点删除了一个关键字
+    Coalesce,
+    /// 从兄弟节点借了一个元素
+    Redistribute,
+    /// 根节点只剩一个孩子或者变为空，树的高度减一
+    AdjustRoot
+}
+
+impl StructuralEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            StructuralEvent::LeafSplit => "leaf_split",
+            StructuralEvent::InsertIntoParent => "insert_into_parent",
+            StructuralEvent::Coalesce => "coalesce",
+            StructuralEvent::Redistribute => "redistribute",
+            StructuralEvent::AdjustRoot => "adjust_root"
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FrameFormat {
+    /// to_dot()的输出，发生变化的节点高亮显示，标题为"#序号 事件名"
+    Dot,
+    /// {"sequence": 序号, "event": 事件名, "changed": [page_id...], "tree": to_json()的输出}
+    Json
+}
+
+impl FrameFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            FrameFormat::Dot => "dot",
+            FrameFormat::Json => "json"
+        }
+    }
+}
+
+/// 一次结构变化之后整棵树的快照，sequence从1开始连续编号
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+    pub sequence: usize,
+    pub event: StructuralEvent,
+    pub changed_page_ids: Vec<usize>,
+    pub format: FrameFormat,
+    pub content: String
+}
+
+impl Frame {
+    /// 例如frame_0003_coalesce.dot，按文件名排序即为帧的顺序
+    pub fn file_name(&self) -> String {
+        format!("frame_{:04}_{}.{}", self.sequence, self.event.name(), self.format.extension())
+    }
+}
+
+/// 把每一帧写入dir下的单独文件，dir不存在时会自动创建
+///
+/// DOT格式的帧可以用`dot -Tpng`逐帧转换为图片，再合成为动画
+pub fn write_frames(frames: &[Frame], dir: impl AsRef<Path>) -> io::Result<()> {
+    fs::create_dir_all(dir.as_ref())?;
+    for frame in frames {
+        fs::write(dir.as_ref().join(frame.file_name()), frame.content.as_bytes())?;
+    }
+    Ok(())
+}
+
+pub(crate) struct FrameRecorder {
+    format: FrameFormat,
+    frames: Vec<Frame>
+}
+
+impl FrameRecorder {
+    pub(crate) fn new(format: FrameFormat) -> Self {
+        FrameRecorder {
+            format,
+            frames: Vec::new()
+        }
+    }
+
+    pub(crate) fn record(&mut self, tree: &BPlusTree, event: StructuralEvent, changed_page_ids: Vec<usize>) {
+        let sequence = self.frames.len() + 1;
+        let content = match self.format {
+            FrameFormat::Dot => {
+                tree.to_dot_with_options(&DotOptions {
+                    title: Some(format!("#{} {}", sequence, event.name())),
+                    highlighted_page_ids: changed_page_ids.clone(),
+                    ..Default::default()
+                })
+            }
+            FrameFormat::Json => {
+                format!("{{\"sequence\":{},\"event\":\"{}\",\"changed\":{:?},\"tree\":{}}}", sequence, event.name(), changed_page_ids, tree.to_json())
+            }
+        };
+
+        self.frames.push(Frame {
+            sequence,
+            event,
+            changed_page_ids,
+            format: self.format,
+            content
+        });
+    }
+
+    pub(crate) fn into_frames(self) -> Vec<Frame> {
+        self.frames
+    }
+}
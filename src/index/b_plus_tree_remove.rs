@@ -0,0 +1,94 @@
+//! 批量删除
+//!
+//! remove_range()在区间的两个边界上各做一次split_off()，整个区间所在的叶子节点和子树被一起丢弃，
+//! 再把右边的部分append()回来，只在两个边界上修复一次，不会像逐个remove()那样每次都触发
+//! coalesce_or_redistribute
+
+use std::ops::{Bound, RangeBounds};
+use crate::index::b_plus_tree::{BPlusTree, BPlusTreeError};
+use crate::index::b_plus_tree_trace::TraceOp;
+use crate::iterator::b_plus_tree_iterator::BPlusTreeIntoIter;
+use crate::page::b_plus_tree_page::SizeT;
+
+impl BPlusTree {
+    /// 删除range中的所有key，返回删除的个数
+    pub fn remove_range(&mut self, range: impl RangeBounds<i32>) -> SizeT {
+        let result = self.cut_range(range).map(|removed| removed.len());
+        self.expect_ok(result)
+    }
+
+    /// 删除range中的所有key，按key从小到大返回删除的键值对
+    pub fn drain(&mut self, range: impl RangeBounds<i32>) -> BPlusTreeIntoIter {
+        let result = self.cut_range(range).map(|removed| removed.into_iter());
+        self.expect_ok(result)
+    }
+
+    /// 只保留f返回true的键值对，f可以修改value，与BTreeMap::retain()一致
+    ///
+    /// 连续的一段被删除的key用一次remove_range()删除
+    pub fn retain(&mut self, mut f: impl FnMut(&i32, &mut i32) -> bool) {
+        let mut removed_runs: Vec<(i32, i32)> = Vec::new();
+        let mut last_kept = true;
+        for (key, value) in self.entries_mut() {
+            let keep = f(key, value);
+            if !keep {
+                match removed_runs.last_mut() {
+                    Some((_, run_end)) if !last_kept => {
+                        *run_end = *key;
+                    }
+                    _ => {
+                        removed_runs.push((*key, *key));
+                    }
+                }
+            }
+            last_kept = keep;
+        }
+
+        // 修改过的value由entries_mut()在下一次记录操作时补记为update
+        self.flush_value_updates();
+        for (run_start, run_end) in removed_runs {
+            self.remove_range(run_start..=run_end);
+        }
+    }
+}
+
+impl BPlusTree {
+    /// 把range中的键值对拆分到一棵新树中并返回
+    fn cut_range(&mut self, range: impl RangeBounds<i32>) -> Result<BPlusTree, BPlusTreeError> {
+        // 转换成左闭右开的区间[start, end)，end为None表示没有上界
+        let start = match range.start_bound() {
+            Bound::Included(start) => Some(*start),
+            Bound::Excluded(start) => start.checked_add(1),
+            Bound::Unbounded => Some(i32::MIN)
+        };
+        let end = match range.end_bound() {
+            Bound::Included(end) => end.checked_add(1),
+            Bound::Excluded(end) => Some(*end),
+            Bound::Unbounded => None
+        };
+        let start = match start {
+            Some(start) if end.is_none_or(|end| start < end) => start,
+            // 区间为空
+            _ => {
+                return BPlusTree::try_new(self.get_index_name().to_string(), self.get_internal_max_size(), self.get_leaf_max_size());
+            }
+        };
+
+        // 回放日志时用逐个删除代替拆分
+        let mut removed_ops = Vec::new();
+        if self.is_tracing() {
+            removed_ops = self.entries().map(|(key, _)| *key)
+                .filter(|key| *key >= start && end.is_none_or(|end| *key < end))
+                .map(|key| TraceOp::Remove { key, removed: true }).collect();
+        }
+
+        self.traced_expanded(removed_ops, |tree| {
+            let mut removed = tree.split_pages(start)?;
+            if let Some(end) = end {
+                let mut right_tree = removed.split_pages(end)?;
+                tree.adopt_pages(&mut right_tree, true)?;
+            }
+            Ok(removed)
+        })
+    }
+}
@@ -0,0 +1,315 @@
+use std::collections::HashSet;
+use std::io;
+use std::io::Write;
+use crate::index::b_plus_tree::BPlusTree;
+use crate::index::b_plus_tree_visitor::{PageView, PageVisitor, WalkOrder};
+
+/// 渲染时节点使用的颜色，取值为Graphviz支持的颜色名或者"#rrggbb"
+#[derive(Clone, Debug, PartialEq)]
+pub struct DotColorScheme {
+    pub internal_color: String,
+    pub leaf_color: String,
+    /// DotOptions::highlighted_page_ids中节点使用的颜色
+    pub highlight_color: String
+}
+
+impl Default for DotColorScheme {
+    fn default() -> Self {
+        DotColorScheme {
+            internal_color: String::from("pink"),
+            leaf_color: String::from("green"),
+            highlight_color: String::from("red")
+        }
+    }
+}
+
+/// BPlusTree::render_dot_with_options()的渲染选项
+#[derive(Clone, Debug, PartialEq)]
+pub struct DotOptions {
+    /// 显示在图上方的标题，None表示不显示
+    pub title: Option<String>,
+    pub color_scheme: DotColorScheme,
+    /// 是否在每个节点中显示max_size和min_size
+    pub show_sizes: bool,
+    /// 是否画出叶子节点之间的next指针
+    pub show_next_links: bool,
+    /// 需要高亮显示的节点
+    pub highlighted_page_ids: Vec<usize>
+}
+
+impl Default for DotOptions {
+    fn default() -> Self {
+        DotOptions {
+            title: None,
+            color_scheme: DotColorScheme::default(),
+            show_sizes: true,
+            show_next_links: true,
+            highlighted_page_ids: Vec::new()
+        }
+    }
+}
+
+const TABLE_START: &str = "label=<<TABLE BORDER=\"0\" CELLBORDER=\"1\" CELLSPACING=\"0\" CELLPADDING=\"4\">\n";
+
+/// 生成Graphviz DOT格式，叶子节点前缀为LEAF_，内部节点前缀为INT_
+struct DotVisitor<'a> {
+    options: &'a DotOptions,
+    graph_str: String,
+    internal_page_ids: HashSet<usize>
+}
+
+impl DotVisitor<'_> {
+    fn page_color<'b>(&'b self, page: &PageView, color: &'b str) -> &'b str {
+        if self.options.highlighted_page_ids.contains(&page.page_id) {
+            &self.options.color_scheme.highlight_color
+        } else {
+            color
+        }
+    }
+}
+
+impl PageVisitor for DotVisitor<'_> {
+    fn enter_page(&mut self, page: &PageView) {
+        let size = page.get_size();
+
+        if page.is_leaf_page() {
+            self.graph_str.push_str(format!("LEAF_{}[shape=plain color=\"{}\" {}", page.page_id, self.page_color(page, &self.options.color_scheme.leaf_color), TABLE_START).as_str());
+            self.graph_str.push_str(format!("<TR><TD COLSPAN=\"{}\">P={}</TD></TR>\n", size, page.page_id).as_str());
+            if self.options.show_sizes {
+                self.graph_str.push_str(format!("<TR><TD COLSPAN=\"{}\">max_size={},min_size={}</TD></TR>\n", size, page.max_size, page.min_size).as_str());
+            }
+            self.graph_str.push_str("<TR>");
+            for key in &page.keys {
+                self.graph_str.push_str(format!("<TD>{}</TD>\n", key).as_str());
+            }
+            self.graph_str.push_str("</TR></TABLE>>];\n");
+
+            // print Leaf node link if there is a next page
+            if let (true, Some(next_id)) = (self.options.show_next_links, page.next_id) {
+                self.graph_str.push_str(format!("LEAF_{} -> LEAF_{};\n{{rank=same LEAF_{} LEAF_{}}};\n", page.page_id, next_id, page.page_id, next_id).as_str());
+            }
+            if let Some(parent_id) = page.parent_id {
+                self.graph_str.push_str(format!("INT_{}:p{} -> LEAF_{};\n", parent_id, page.page_id, page.page_id).as_str());
+            }
+        } else if page.is_internal_page() {
+            self.internal_page_ids.insert(page.page_id);
+
+            self.graph_str.push_str(format!("INT_{}[shape=plain color=\"{}\" {}", page.page_id, self.page_color(page, &self.options.color_scheme.internal_color), TABLE_START).as_str());
+            self.graph_str.push_str(format!("<TR><TD COLSPAN=\"{}\">P=\"{}\"</TD></TR>\n", size, page.page_id).as_str());
+            if self.options.show_sizes {
+                self.graph_str.push_str(format!("<TR><TD COLSPAN=\"{}\">max_size={},min_size={}</TD></TR>\n", size, page.max_size, page.min_size).as_str());
+            }
+            self.graph_str.push_str("<TR>");
+            for (i, child_id) in page.child_ids.iter().enumerate() {
+                // 下标为0的key不提供检索功能，不显示
+                let key = if i > 0 { page.keys[i - 1].to_string() } else { String::from(" ") };
+                self.graph_str.push_str(format!("<TD PORT=\"p{}\">{}</TD>\n", child_id, key).as_str());
+            }
+            self.graph_str.push_str("</TR></TABLE>>];\n");
+
+            if let Some(parent_id) = page.parent_id {
+                self.graph_str.push_str(format!("INT_{}:p{} -> INT_{};\n", parent_id, page.page_id, page.page_id).as_str());
+            }
+        }
+    }
+
+    fn leave_page(&mut self, page: &PageView) {
+        // 同一个父节点下相邻的内部节点画在同一层
+        for pair in page.child_ids.windows(2) {
+            if self.internal_page_ids.contains(&pair[0]) && self.internal_page_ids.contains(&pair[1]) {
+                self.graph_str.push_str(format!("{{rank=same INT_{} INT_{}}};\n", pair[0], pair[1]).as_str());
+            }
+        }
+    }
+}
+
+/// print()使用的文本格式
+#[derive(Default)]
+struct TextVisitor {
+    text: String
+}
+
+impl PageVisitor for TextVisitor {
+    fn enter_page(&mut self, page: &PageView) {
+        let parent_page_id = page.parent_id.map_or(String::from("None"), |id| id.to_string());
+        let next_page_id = page.next_id.map_or(String::from("None"), |id| id.to_string());
+
+        if page.is_leaf_page() {
+            self.text.push_str(format!("Leaf Page: {} Parent: {} Next: {}\n", page.page_id, parent_page_id, next_page_id).as_str());
+            for key in &page.keys {
+                self.text.push_str(format!("{}, ", key).as_str());
+            }
+        } else {
+            self.text.push_str(format!("Internal Page: {} Parent: {} Next: {}\n", page.page_id, parent_page_id, next_page_id).as_str());
+            for (i, child_id) in page.child_ids.iter().enumerate() {
+                let key = if i > 0 { page.keys[i - 1].to_string() } else { String::from("-") };
+                self.text.push_str(format!("{} : {}, ", key, child_id).as_str());
+            }
+        }
+        self.text.push_str("\n\n");
+    }
+}
+
+/// Mermaid flowchart，内部节点为方框，叶子节点为圆角框，叶子节点之间的next指针用虚线表示
+struct MermaidVisitor {
+    chart: String
+}
+
+impl PageVisitor for MermaidVisitor {
+    fn enter_page(&mut self, page: &PageView) {
+        let keys: Vec<String> = page.keys.iter().map(|key| key.to_string()).collect();
+        if page.is_leaf_page() {
+            self.chart.push_str(format!("    P{}(\"P{}: {}\")\n", page.page_id, page.page_id, keys.join(", ")).as_str());
+            if let Some(next_id) = page.next_id {
+                self.chart.push_str(format!("    P{} -.-> P{}\n", page.page_id, next_id).as_str());
+            }
+        } else {
+            self.chart.push_str(format!("    P{}[\"P{}: {}\"]\n", page.page_id, page.page_id, keys.join(" | ")).as_str());
+            for child_id in &page.child_ids {
+                self.chart.push_str(format!("    P{} --> P{}\n", page.page_id, child_id).as_str());
+            }
+        }
+    }
+}
+
+/// 嵌套的JSON，内部节点的children按从左到右的顺序包含子节点对象
+#[derive(Default)]
+struct JsonVisitor {
+    json: String,
+    // 每一层已经输出的子节点个数，用来决定是否需要逗号
+    child_counts: Vec<usize>
+}
+
+impl PageVisitor for JsonVisitor {
+    fn enter_page(&mut self, page: &PageView) {
+        if let Some(child_count) = self.child_counts.last_mut() {
+            if *child_count > 0 {
+                self.json.push(',');
+            }
+            *child_count += 1;
+        }
+
+        let page_type = if page.is_leaf_page() { "leaf" } else { "internal" };
+        self.json.push_str(format!("{{\"page_id\":{},\"type\":\"{}\",\"level\":{},\"keys\":{:?}", page.page_id, page_type, page.level, page.keys).as_str());
+        if page.is_leaf_page() {
+            let next_id = page.next_id.map_or(String::from("null"), |id| id.to_string());
+            self.json.push_str(format!(",\"values\":{:?},\"next\":{}}}", page.values, next_id).as_str());
+        } else {
+            self.json.push_str(",\"children\":[");
+            self.child_counts.push(0);
+        }
+    }
+
+    fn leave_page(&mut self, page: &PageView) {
+        if page.is_internal_page() {
+            self.child_counts.pop();
+            self.json.push_str("]}");
+        }
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut json = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            c if (c as u32) < 0x20 => json.push_str(format!("\\u{:04x}", c as u32).as_str()),
+            c => json.push(c)
+        }
+    }
+    json.push('"');
+    json
+}
+
+/// 终端中显示的方框图，每一层一行，叶子节点之间用-->连接
+#[derive(Default)]
+struct AsciiVisitor {
+    // levels[i]保存第i层从左到右每个节点的(标签, 是否有next指针)
+    levels: Vec<Vec<(String, bool)>>
+}
+
+impl PageVisitor for AsciiVisitor {
+    fn enter_page(&mut self, page: &PageView) {
+        if self.levels.len() <= page.level {
+            self.levels.resize_with(page.level + 1, Vec::new);
+        }
+        let keys: Vec<String> = page.keys.iter().map(|key| key.to_string()).collect();
+        self.levels[page.level].push((format!("P{}: {}", page.page_id, keys.join(" ")), page.next_id.is_some()));
+    }
+}
+
+impl AsciiVisitor {
+    fn render(&self) -> String {
+        let mut ascii = String::new();
+        for pages in &self.levels {
+            let (mut top, mut middle) = (String::new(), String::new());
+            for (i, (label, has_next)) in pages.iter().enumerate() {
+                let border = format!("+{}+", "-".repeat(label.len() + 2));
+                top.push_str(border.as_str());
+                middle.push_str(format!("| {} |", label).as_str());
+                if i + 1 < pages.len() {
+                    top.push_str("   ");
+                    middle.push_str(if *has_next { "-->" } else { "   " });
+                }
+            }
+            ascii.push_str(format!("{}\n{}\n{}\n", top, middle, top).as_str());
+        }
+        ascii
+    }
+}
+
+impl BPlusTree {
+    /// 以Graphviz DOT格式把整棵树写入w，使用默认的渲染选项
+    pub fn render_dot(&self, w: &mut impl Write) -> io::Result<()> {
+        self.render_dot_with_options(w, &DotOptions::default())
+    }
+
+    pub fn render_dot_with_options(&self, w: &mut impl Write, options: &DotOptions) -> io::Result<()> {
+        w.write_all(self.to_dot_with_options(options).as_bytes())
+    }
+
+    pub fn to_dot(&self) -> String {
+        self.to_dot_with_options(&DotOptions::default())
+    }
+
+    pub fn to_dot_with_options(&self, options: &DotOptions) -> String {
+        let mut visitor = DotVisitor { options, graph_str: String::from("digraph G {\n"), internal_page_ids: HashSet::new() };
+        if let Some(title) = &options.title {
+            visitor.graph_str.push_str(format!("label=\"{}\";\nlabelloc=t;\n", title.replace('"', "\\\"")).as_str());
+        }
+        self.walk(WalkOrder::DepthFirst, &mut visitor);
+        visitor.graph_str.push_str("}\n");
+        visitor.graph_str
+    }
+
+    /// Mermaid flowchart，可以直接嵌入Markdown文档的```mermaid代码块中
+    pub fn to_mermaid(&self) -> String {
+        let mut visitor = MermaidVisitor { chart: String::from("flowchart TD\n") };
+        self.walk(WalkOrder::DepthFirst, &mut visitor);
+        visitor.chart
+    }
+
+    /// {"index_name": ..., "root": 根节点对象或null}，每个节点对象包含page_id、type、level、keys，
+    /// 叶子节点还包含values和next，内部节点还包含children
+    pub fn to_json(&self) -> String {
+        let mut visitor = JsonVisitor::default();
+        self.walk(WalkOrder::DepthFirst, &mut visitor);
+        if visitor.json.is_empty() {
+            visitor.json.push_str("null");
+        }
+        format!("{{\"index_name\":{},\"root\":{}}}", json_string(self.get_index_name()), visitor.json)
+    }
+
+    pub fn to_ascii(&self) -> String {
+        let mut visitor = AsciiVisitor::default();
+        self.walk(WalkOrder::DepthFirst, &mut visitor);
+        visitor.render()
+    }
+
+    pub(crate) fn to_text(&self) -> String {
+        let mut visitor = TextVisitor::default();
+        self.walk(WalkOrder::DepthFirst, &mut visitor);
+        visitor.text
+    }
+}
@@ -0,0 +1,315 @@
+//! 整棵树的拆分与拼接
+//!
+//! split_off()沿着根节点到叶子节点的路径把每个节点一分为二，append()把较矮的树作为一个孩子挂到较高的树中
+//! 同一高度的边界节点旁边，两者都只修改边界路径上的节点，再用coalesce_or_redistribute修复下溢的节点，
+//! 所以只需要O(log n)次节点操作
+
+use std::rc::Rc;
+use crate::index::b_plus_tree::{BPlusTree, BPlusTreeError, TreeCorruption};
+use crate::index::b_plus_tree_trace::TraceOp;
+use crate::page::b_plus_tree_page::{BPlusTreePage, RcPage, SizeT};
+use crate::page::b_plus_tree_page::BPlusTreePageType::{InternalPage, LeafPage};
+
+impl BPlusTree {
+    /// 把大于等于key的键值对拆分到一棵新树中并返回，新树的名字、节点大小、聚合函数和合并函数与原来的树相同
+    pub fn split_off(&mut self, key: i32) -> BPlusTree {
+        let result = self.try_split_off(key);
+        self.expect_ok(result)
+    }
+
+    /// 把other中的键值对全部移到当前树中，other变为空树
+    ///
+    /// other中所有的key都必须大于当前树中所有的key，否则panic，key有重叠时使用merge()
+    pub fn append(&mut self, other: &mut BPlusTree) {
+        if let (Some((last_key, _)), Some((first_key, _))) = (self.last_key_value(), other.first_key_value()) {
+            assert!(last_key < first_key, "append() requires every key of other to be greater than the keys of self");
+        }
+        let result = self.adopt(other, true);
+        self.expect_ok(result)
+    }
+
+    /// 把other中的键值对全部移到当前树中，other变为空树，两棵树中都有的key使用other中的value
+    ///
+    /// other中的key全部大于或者全部小于当前树中的key时与append()一样只需要O(log n)次节点操作，
+    /// 否则逐个插入other中的键值对
+    pub fn merge(&mut self, other: &mut BPlusTree) {
+        let result = self.merge_from(other);
+        self.expect_ok(result)
+    }
+}
+
+impl BPlusTree {
+    fn try_split_off(&mut self, key: i32) -> Result<BPlusTree, BPlusTreeError> {
+        // 回放日志时用逐个删除代替拆分
+        let mut moved_ops = Vec::new();
+        if self.is_tracing() {
+            moved_ops = self.entries().map(|(moved_key, _)| *moved_key).filter(|moved_key| *moved_key >= key)
+                .map(|moved_key| TraceOp::Remove { key: moved_key, removed: true }).collect();
+        }
+        self.traced_expanded(moved_ops, |tree| tree.split_pages(key))
+    }
+
+    /// 与split_off()相同，但是不记录操作日志
+    pub(crate) fn split_pages(&mut self, key: i32) -> Result<BPlusTree, BPlusTreeError> {
+        let mut right_tree = BPlusTree::try_new(self.get_index_name().to_string(), self.get_internal_max_size(), self.get_leaf_max_size())?;
+        right_tree.replace_aggregator(self.get_aggregator().cloned())?;
+        right_tree.replace_merge_operator(self.get_merge_operator().cloned());
+        self.rebuild_dirty_aggregates()?;
+        let root_page = match self.get_root_page() {
+            None => {
+                return Ok(right_tree);
+            }
+            Some(root_page) => {
+                root_page
+            }
+        };
+
+        let mut path = Vec::new();
+        let mut cur_page = root_page;
+        while cur_page.borrow().is_internal_page() {
+            let (index, _) = cur_page.borrow().lookup_traced(key, |_, _| {});
+            let index = index.ok_or_else(|| TreeCorruption::InvalidPage { page_id: cur_page.borrow().get_page_id() })?;
+            let child_page = Self::child_page_at(&cur_page, index)?;
+            path.push((cur_page, index));
+            cur_page = child_page;
+        }
+
+        // 原来的节点留在左边，右半部分移到新节点中，变为空的节点直接丢弃
+        let leaf_page = cur_page;
+        let right_leaf_page = BPlusTreePage::new(LeafPage, self.get_leaf_max_size(), None);
+        let index = leaf_page.borrow().key_index(key);
+        leaf_page.borrow_mut().move_tail_to(index, right_leaf_page.clone())?;
+        right_leaf_page.borrow_mut().set_next_page(leaf_page.borrow().get_next_page());
+        leaf_page.borrow_mut().set_next_page(None);
+        let mut left_piece = Some(leaf_page).filter(|page| page.borrow().get_size() > 0);
+        let mut right_piece = Some(right_leaf_page).filter(|page| page.borrow().get_size() > 0);
+
+        // 下标为index的孩子已经拆成了left_piece和right_piece，left_piece一定是原来的孩子
+        for (page, index) in path.into_iter().rev() {
+            let right_page = BPlusTreePage::new(InternalPage, self.get_internal_max_size(), None);
+            page.borrow_mut().move_tail_to(index + 1, right_page.clone())?;
+            match &left_piece {
+                None => {
+                    page.borrow_mut().remove(index);
+                }
+                Some(left_child_page) => {
+                    self.refresh_summary_in_parent(left_child_page)?;
+                }
+            }
+            if let Some(right_child_page) = right_piece {
+                // 下标为0的key没有意义，原来下标为index + 1的孩子的key正好是两个孩子之间的分隔key
+                right_page.borrow_mut().insert_node_at(0, Default::default(), right_child_page.clone());
+                right_child_page.borrow_mut().set_parent_page(Some(right_page.clone()));
+                self.refresh_summary_in_parent(&right_child_page)?;
+            }
+            left_piece = Some(page).filter(|page| page.borrow().get_size() > 0);
+            right_piece = Some(right_page).filter(|page| page.borrow().get_size() > 0);
+        }
+
+        // key左边的叶子节点都被丢弃时，左边的树中最后一个叶子节点仍然指向右边的树
+        if let Some(left_root_page) = &left_piece {
+            if let Some(last_leaf_page) = Self::spine_of(left_root_page.clone(), false)?.last() {
+                last_leaf_page.borrow_mut().set_next_page(None);
+            }
+        }
+
+        let right_len = right_piece.as_ref().map_or(0, |page| page.borrow().get_subtree_count());
+        let left_len = self.len() - right_len;
+        self.replace_root(left_piece, left_len);
+        right_tree.replace_root(right_piece, right_len);
+        self.without_structural_stats(|tree| tree.repair_spine(false))?;
+        right_tree.without_structural_stats(|tree| tree.repair_spine(true))?;
+        self.check_invariants()?;
+        right_tree.check_invariants()?;
+        Ok(right_tree)
+    }
+
+    fn merge_from(&mut self, other: &mut BPlusTree) -> Result<(), BPlusTreeError> {
+        let (other_first, other_last) = match (other.first_key_value(), other.last_key_value()) {
+            (Some((first_key, _)), Some((last_key, _))) => (*first_key, *last_key),
+            _ => {
+                return Ok(());
+            }
+        };
+        let (self_first, self_last) = match (self.first_key_value(), self.last_key_value()) {
+            (Some((first_key, _)), Some((last_key, _))) => (*first_key, *last_key),
+            _ => {
+                return self.adopt(other, true);
+            }
+        };
+
+        if self_last < other_first {
+            return self.adopt(other, true);
+        }
+        if other_last < self_first {
+            return self.adopt(other, false);
+        }
+
+        let entries: Vec<(i32, i32)> = other.entries().map(|(key, value)| (*key, *value)).collect();
+        for (key, value) in entries {
+            if !self.try_insert(key, value)? {
+                self.update_value(key, value)?;
+            }
+        }
+        other.clear();
+        Ok(())
+    }
+
+    /// 接管other中的所有节点，other_is_greater表示other中的key全部大于当前树中的key，否则全部小于
+    fn adopt(&mut self, other: &mut BPlusTree, other_is_greater: bool) -> Result<(), BPlusTreeError> {
+        if other.is_empty() {
+            return Ok(());
+        }
+        // 节点大小不同时不能直接拼接节点
+        if self.get_internal_max_size() != other.get_internal_max_size() || self.get_leaf_max_size() != other.get_leaf_max_size() {
+            let entries: Vec<(i32, i32)> = other.entries().map(|(key, value)| (*key, *value)).collect();
+            for (key, value) in entries {
+                self.try_insert(key, value)?;
+            }
+            other.clear();
+            return Ok(());
+        }
+
+        // 回放日志时用逐个插入代替拼接
+        let mut adopted_ops = Vec::new();
+        if self.is_tracing() {
+            adopted_ops = other.entries().map(|(key, value)| TraceOp::Insert { key: *key, value: *value, inserted: true }).collect();
+        }
+        self.traced_expanded(adopted_ops, |tree| tree.adopt_pages(other, other_is_greater))
+    }
+
+    /// 与adopt()相同，但是不记录操作日志，并且要求两棵树的节点大小相同
+    pub(crate) fn adopt_pages(&mut self, other: &mut BPlusTree, other_is_greater: bool) -> Result<(), BPlusTreeError> {
+        if other.is_empty() {
+            return Ok(());
+        }
+
+        // other中缓存的聚合值必须是用当前树的聚合函数计算的
+        self.rebuild_dirty_aggregates()?;
+        if let Some(aggregator) = self.get_aggregator() {
+            let same_aggregator = other.get_aggregator().is_some_and(|other_aggregator| Rc::ptr_eq(other_aggregator, aggregator));
+            if same_aggregator {
+                other.rebuild_dirty_aggregates()?;
+            } else {
+                other.replace_aggregator(Some(aggregator.clone()))?;
+            }
+        }
+
+        let len = self.len() + other.len();
+        let other_root_page = other.get_root_page();
+        other.clear();
+        match (self.get_root_page(), other_root_page) {
+            (_, None) => {}
+            (None, other_root_page) => {
+                self.replace_root(other_root_page, len);
+            }
+            (Some(self_root_page), Some(other_root_page)) => {
+                if other_is_greater {
+                    self.without_structural_stats(|tree| tree.join(self_root_page, other_root_page, len))?;
+                } else {
+                    self.without_structural_stats(|tree| tree.join(other_root_page, self_root_page, len))?;
+                }
+            }
+        }
+        self.check_invariants()?;
+        Ok(())
+    }
+
+    /// 拼接两棵子树作为当前树，right_root_page中的key全部大于left_root_page中的key
+    fn join(&mut self, left_root_page: RcPage, right_root_page: RcPage, len: SizeT) -> Result<(), BPlusTreeError> {
+        let left_spine = Self::spine_of(left_root_page.clone(), false)?;
+        let right_spine = Self::spine_of(right_root_page.clone(), true)?;
+        let (left_leaf_page, right_leaf_page) = match (left_spine.last(), right_spine.last()) {
+            (Some(left_leaf_page), Some(right_leaf_page)) => (left_leaf_page, right_leaf_page),
+            _ => {
+                return Err(TreeCorruption::InvalidPage { page_id: left_root_page.borrow().get_page_id() }.into());
+            }
+        };
+        left_leaf_page.borrow_mut().set_next_page(Some(right_leaf_page.clone()));
+        let middle_key = right_leaf_page.borrow().key_at(0);
+
+        // 较矮的树的根节点挂到较高的树中同一高度的边界节点旁边，之后只有这两个节点可能下溢
+        let (left_page, right_page) = if left_spine.len() >= right_spine.len() {
+            let left_page = left_spine[left_spine.len() - right_spine.len()].clone();
+            self.replace_root(Some(left_root_page), len);
+            self.insert_into_parent(left_page.clone(), middle_key, right_root_page.clone())?;
+            (left_page, right_root_page)
+        } else {
+            let right_page = right_spine[right_spine.len() - left_spine.len()].clone();
+            self.replace_root(Some(right_root_page), len);
+            self.insert_into_parent_front(left_root_page.clone(), middle_key, right_page.clone())?;
+            (left_root_page, right_page)
+        };
+
+        for page in [left_page, right_page] {
+            // 被合并到兄弟节点之后page会变为空节点
+            while !page.borrow().is_root_page() && page.borrow().get_size() > 0 && page.borrow().get_size() < page.borrow().get_min_size() {
+                self.coalesce_or_redistribute(page.clone())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// 把new_page插入到old_page的父节点中作为第一个孩子，old_page原来是第一个孩子
+    fn insert_into_parent_front(&mut self, new_page: RcPage, middle_key: i32, old_page: RcPage) -> Result<(), BPlusTreeError> {
+        let parent_page = Self::parent_page_of(&old_page)?;
+        let new_size = parent_page.borrow_mut().insert_node_at(0, Default::default(), new_page.clone());
+        parent_page.borrow_mut().set_key_at(1, middle_key);
+        new_page.borrow_mut().set_parent_page(Some(parent_page.clone()));
+        self.refresh_summary_in_parent(&new_page)?;
+
+        // -1是去掉下标为0的item
+        if new_size - 1 < self.get_internal_max_size() {
+            return self.refresh_summaries_to_root(&parent_page);
+        }
+
+        let new_parent_sibling_node = self.split(parent_page.clone())?;
+        let middle_key = new_parent_sibling_node.borrow().key_at(0);
+        self.insert_into_parent(parent_page, middle_key, new_parent_sibling_node)
+    }
+
+    /// 拆分之后只有最左边（left_most为true）或者最右边路径上的节点可能下溢，
+    /// 每次修复最上面的下溢节点，保证修复时它的父节点至少有两个孩子
+    fn repair_spine(&mut self, left_most: bool) -> Result<(), BPlusTreeError> {
+        loop {
+            let root_page = match self.get_root_page() {
+                None => {
+                    return Ok(());
+                }
+                Some(root_page) => {
+                    root_page
+                }
+            };
+            // 根节点只有一个孩子时降低树的高度
+            if self.coalesce_or_redistribute(root_page.clone())? {
+                continue;
+            }
+
+            let underflow_page = Self::spine_of(root_page, left_most)?.into_iter().skip(1)
+                .find(|page| page.borrow().get_size() < page.borrow().get_min_size());
+            match underflow_page {
+                None => {
+                    return Ok(());
+                }
+                Some(underflow_page) => {
+                    self.coalesce_or_redistribute(underflow_page)?;
+                }
+            }
+        }
+    }
+
+    /// 从根节点一直到最左边（left_most为true）或者最右边的叶子节点的路径
+    fn spine_of(root_page: RcPage, left_most: bool) -> Result<Vec<RcPage>, BPlusTreeError> {
+        let mut spine = vec![root_page.clone()];
+        let mut cur_page = root_page;
+        while cur_page.borrow().is_internal_page() {
+            let size = cur_page.borrow().get_size();
+            if size == 0 {
+                return Err(TreeCorruption::InvalidPage { page_id: cur_page.borrow().get_page_id() }.into());
+            }
+            cur_page = Self::child_page_at(&cur_page, if left_most { 0 } else { size - 1 })?;
+            spine.push(cur_page.clone());
+        }
+        Ok(spine)
+    }
+}
@@ -0,0 +1,88 @@
+use std::fmt::{Display, Formatter};
+use crate::index::b_plus_tree_visitor::{PageView, PageVisitor};
+use crate::page::b_plus_tree_page::SizeT;
+
+/// 某一层节点的统计信息，fill factor = 元素个数 / 节点最大容量
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LevelStats {
+    pub page_count: SizeT,
+    pub entry_count: SizeT,
+    pub average_fill_factor: f64,
+    pub min_fill_factor: f64
+}
+
+/// BPlusTree::stats()的返回值，levels[0]是根节点所在的层
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TreeStats {
+    pub height: SizeT,
+    pub internal_page_count: SizeT,
+    pub leaf_page_count: SizeT,
+    pub entry_count: SizeT,
+    pub levels: Vec<LevelStats>,
+    pub split_count: SizeT,
+    pub merge_count: SizeT,
+    pub redistribute_count: SizeT
+}
+
+impl Display for TreeStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "height: {}", self.height)?;
+        writeln!(f, "internal pages: {}, leaf pages: {}, entries: {}", self.internal_page_count, self.leaf_page_count, self.entry_count)?;
+        writeln!(f, "splits: {}, merges: {}, redistributions: {}", self.split_count, self.merge_count, self.redistribute_count)?;
+        for (level, level_stats) in self.levels.iter().enumerate() {
+            writeln!(f, "level {}: pages: {}, entries: {}, fill factor: avg {:.2} min {:.2}", level, level_stats.page_count,
+                     level_stats.entry_count, level_stats.average_fill_factor, level_stats.min_fill_factor)?;
+        }
+        Ok(())
+    }
+}
+
+/// 逐个节点累加每一层的统计信息，最后由finish()计算平均填充率和高度
+#[derive(Default)]
+pub(crate) struct StatsVisitor {
+    levels: Vec<LevelStats>,
+    fill_factor_sums: Vec<f64>,
+    internal_page_count: SizeT,
+    leaf_page_count: SizeT,
+    entry_count: SizeT
+}
+
+impl PageVisitor for StatsVisitor {
+    fn enter_page(&mut self, page: &PageView) {
+        if self.levels.len() <= page.level {
+            self.levels.resize(page.level + 1, LevelStats { min_fill_factor: 1.0, ..Default::default() });
+            self.fill_factor_sums.resize(page.level + 1, 0.0);
+        }
+
+        // 叶子节点最多只能保存max_size - 1个元素
+        let capacity = if page.is_leaf_page() { page.max_size - 1 } else { page.max_size };
+        let fill_factor = page.get_size() as f64 / capacity as f64;
+        let level_stats = &mut self.levels[page.level];
+        level_stats.page_count += 1;
+        level_stats.entry_count += page.get_size();
+        level_stats.min_fill_factor = f64::min(level_stats.min_fill_factor, fill_factor);
+        self.fill_factor_sums[page.level] += fill_factor;
+
+        if page.is_leaf_page() {
+            self.leaf_page_count += 1;
+            self.entry_count += page.get_size();
+        } else {
+            self.internal_page_count += 1;
+        }
+    }
+}
+
+impl StatsVisitor {
+    /// 把统计结果填入stats，stats中的split/merge/redistribute次数保持不变
+    pub(crate) fn finish(mut self, mut stats: TreeStats) -> TreeStats {
+        for (level_stats, fill_factor_sum) in self.levels.iter_mut().zip(self.fill_factor_sums) {
+            level_stats.average_fill_factor = fill_factor_sum / level_stats.page_count as f64;
+        }
+        stats.height = self.levels.len();
+        stats.internal_page_count = self.internal_page_count;
+        stats.leaf_page_count = self.leaf_page_count;
+        stats.entry_count = self.entry_count;
+        stats.levels = self.levels;
+        stats
+    }
+}
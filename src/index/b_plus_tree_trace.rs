@@ -0,0 +1,366 @@
+//! 操作日志的录制和回放
+//!
+//! 日志格式（整数均为小端序）：
+//!
+//! 文件头：b"BPTT" + 版本号(u8) + internal_max_size(u32) + leaf_max_size(u32)
+//!
+//! 每条记录：操作码(u8) + key(i32) + 参数，在执行操作之前写入；执行之后再写入状态(u8)，
+//! 状态为成功时后面跟着结果。在非空的树上开始录制时，最前面是树中已有的每个键值对的insert记录
+//!
+//! | 操作码 | 操作 | 参数 | 结果 |
+//! | --- | --- | --- | --- |
+//! | 1 | insert | value(i32) | 是否插入成功(u8) |
+//! | 2 | remove | 无 | 是否删除了key(u8) |
+//! | 3 | get | 无 | 是否找到(u8)，找到时再跟value(i32) |
+//! | 4 | update | value(i32) | 是否存在原来的value(u8)，存在时再跟原来的value(i32) |
+//!
+//! 状态：0为成功，1为返回了错误，2为没有执行完（执行时panic）。操作panic之后不会写入状态，
+//! 所以下一条记录开始时或者结束录制时补写2；日志正好在参数之后结束时也按没有执行完处理
+
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::io::{ErrorKind, Read, Write};
+use std::panic::{self, AssertUnwindSafe};
+use crate::index::b_plus_tree::{BPlusTree, BPlusTreeError, TreeCorruption};
+use crate::page::b_plus_tree_page::SizeT;
+
+const TRACE_MAGIC: &[u8; 4] = b"BPTT";
+const TRACE_VERSION: u8 = 2;
+
+const INSERT_TAG: u8 = 1;
+const REMOVE_TAG: u8 = 2;
+const GET_TAG: u8 = 3;
+const UPDATE_TAG: u8 = 4;
+
+const COMPLETED_STATUS: u8 = 0;
+const FAILED_STATUS: u8 = 1;
+const INTERRUPTED_STATUS: u8 = 2;
+
+/// 一次公开操作的参数和结果
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TraceOp {
+    Insert { key: i32, value: i32, inserted: bool },
+    Remove { key: i32, removed: bool },
+    Get { key: i32, value: Option<i32> },
+    /// 修改已经存在的key的value，比如Extend遇到重复的key，previous为None表示key不存在
+    Update { key: i32, value: i32, previous: Option<i32> }
+}
+
+/// 日志中一条操作的执行情况
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TraceStatus {
+    Completed,
+    /// 操作返回了错误
+    Failed,
+    /// 操作没有执行完，通常是执行时panic了
+    Interrupted
+}
+
+/// 日志中的一条记录，status不是Completed时op中的结果字段没有意义
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TraceRecord {
+    pub op: TraceOp,
+    pub status: TraceStatus
+}
+
+impl TraceOp {
+    /// 写入操作码、key和参数
+    fn write_args_to(&self, w: &mut impl Write) -> io::Result<()> {
+        match *self {
+            TraceOp::Insert { key, value, .. } | TraceOp::Update { key, value, .. } => {
+                w.write_all(&[self.tag()])?;
+                w.write_all(&key.to_le_bytes())?;
+                w.write_all(&value.to_le_bytes())
+            }
+            TraceOp::Remove { key, .. } | TraceOp::Get { key, .. } => {
+                w.write_all(&[self.tag()])?;
+                w.write_all(&key.to_le_bytes())
+            }
+        }
+    }
+
+    fn write_result_to(&self, w: &mut impl Write) -> io::Result<()> {
+        match *self {
+            TraceOp::Insert { inserted, .. } => w.write_all(&[inserted as u8]),
+            TraceOp::Remove { removed, .. } => w.write_all(&[removed as u8]),
+            TraceOp::Get { value, .. } => write_option(w, value),
+            TraceOp::Update { previous, .. } => write_option(w, previous)
+        }
+    }
+
+    fn tag(&self) -> u8 {
+        match self {
+            TraceOp::Insert { .. } => INSERT_TAG,
+            TraceOp::Remove { .. } => REMOVE_TAG,
+            TraceOp::Get { .. } => GET_TAG,
+            TraceOp::Update { .. } => UPDATE_TAG
+        }
+    }
+
+    /// 读取下一条记录，正好在记录边界处读到文件末尾时返回None
+    fn read_from(r: &mut impl Read) -> io::Result<Option<TraceRecord>> {
+        let mut tag = [0u8; 1];
+        if r.read(&mut tag)? == 0 {
+            return Ok(None);
+        }
+
+        let key = read_i32(r)?;
+        let op = match tag[0] {
+            INSERT_TAG => TraceOp::Insert { key, value: read_i32(r)?, inserted: false },
+            REMOVE_TAG => TraceOp::Remove { key, removed: false },
+            GET_TAG => TraceOp::Get { key, value: None },
+            UPDATE_TAG => TraceOp::Update { key, value: read_i32(r)?, previous: None },
+            tag => {
+                return Err(io::Error::new(ErrorKind::InvalidData, format!("unknown trace op tag {}", tag)));
+            }
+        };
+
+        let mut status = [0u8; 1];
+        if r.read(&mut status)? == 0 {
+            return Ok(Some(TraceRecord { op, status: TraceStatus::Interrupted }));
+        }
+        let record = match status[0] {
+            COMPLETED_STATUS => {
+                let op = match op {
+                    TraceOp::Insert { key, value, .. } => TraceOp::Insert { key, value, inserted: read_bool(r)? },
+                    TraceOp::Remove { key, .. } => TraceOp::Remove { key, removed: read_bool(r)? },
+                    TraceOp::Get { key, .. } => TraceOp::Get { key, value: read_option(r)? },
+                    TraceOp::Update { key, value, .. } => TraceOp::Update { key, value, previous: read_option(r)? }
+                };
+                TraceRecord { op, status: TraceStatus::Completed }
+            }
+            FAILED_STATUS => TraceRecord { op, status: TraceStatus::Failed },
+            INTERRUPTED_STATUS => TraceRecord { op, status: TraceStatus::Interrupted },
+            status => {
+                return Err(io::Error::new(ErrorKind::InvalidData, format!("unknown trace op status {}", status)));
+            }
+        };
+        Ok(Some(record))
+    }
+
+    /// 在tree上重新执行该操作，返回实际得到的结果
+    fn apply(&self, tree: &mut BPlusTree) -> Result<TraceOp, BPlusTreeError> {
+        let op = match *self {
+            TraceOp::Insert { key, value, .. } => TraceOp::Insert { key, value, inserted: tree.try_insert(key, value)? },
+            TraceOp::Remove { key, .. } => TraceOp::Remove { key, removed: tree.try_remove(key)? },
+            TraceOp::Get { key, .. } => TraceOp::Get { key, value: tree.try_get_value(key)? },
+            TraceOp::Update { key, value, .. } => TraceOp::Update { key, value, previous: tree.update_value(key, value)? }
+        };
+        Ok(op)
+    }
+}
+
+fn write_option(w: &mut impl Write, value: Option<i32>) -> io::Result<()> {
+    match value {
+        Some(value) => {
+            w.write_all(&[1])?;
+            w.write_all(&value.to_le_bytes())
+        }
+        None => w.write_all(&[0])
+    }
+}
+
+fn read_option(r: &mut impl Read) -> io::Result<Option<i32>> {
+    if read_bool(r)? { Ok(Some(read_i32(r)?)) } else { Ok(None) }
+}
+
+fn read_i32(r: &mut impl Read) -> io::Result<i32> {
+    let mut buf = [0u8; 4];
+    r.read_exact(&mut buf)?;
+    Ok(i32::from_le_bytes(buf))
+}
+
+fn read_u32(r: &mut impl Read) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_bool(r: &mut impl Read) -> io::Result<bool> {
+    let mut buf = [0u8; 1];
+    r.read_exact(&mut buf)?;
+    Ok(buf[0] != 0)
+}
+
+/// BPlusTree上挂载的日志录制器
+///
+/// 操作本身不能因为写日志失败而失败，所以只保存第一个写入错误，在finish()时返回
+pub(crate) struct TraceWriter {
+    writer: Box<dyn Write>,
+    error: Option<io::Error>,
+    // 已经写入参数、还没有写入状态的操作
+    open: bool
+}
+
+impl TraceWriter {
+    pub(crate) fn new(mut writer: Box<dyn Write>, internal_max_size: SizeT, leaf_max_size: SizeT) -> io::Result<Self> {
+        writer.write_all(TRACE_MAGIC)?;
+        writer.write_all(&[TRACE_VERSION])?;
+        writer.write_all(&(internal_max_size as u32).to_le_bytes())?;
+        writer.write_all(&(leaf_max_size as u32).to_le_bytes())?;
+        Ok(TraceWriter { writer, error: None, open: false })
+    }
+
+    /// 在执行操作之前写入参数，上一条操作没有写入状态时先把它标记为没有执行完
+    pub(crate) fn begin(&mut self, op: TraceOp) {
+        self.close_interrupted();
+        self.write(|w| op.write_args_to(w));
+        self.open = true;
+    }
+
+    /// 操作执行完之后写入状态，result为None表示操作返回了错误
+    pub(crate) fn end(&mut self, result: Option<TraceOp>) {
+        if !self.open {
+            return;
+        }
+        self.open = false;
+        match result {
+            Some(op) => {
+                self.write(|w| {
+                    w.write_all(&[COMPLETED_STATUS])?;
+                    op.write_result_to(w)
+                });
+            }
+            None => {
+                self.write(|w| w.write_all(&[FAILED_STATUS]));
+            }
+        }
+    }
+
+    /// 写入一条已经执行完的操作
+    pub(crate) fn record(&mut self, op: TraceOp) {
+        self.begin(op);
+        self.end(Some(op));
+    }
+
+    pub(crate) fn finish(mut self) -> io::Result<()> {
+        self.close_interrupted();
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => self.writer.flush()
+        }
+    }
+
+    fn close_interrupted(&mut self) {
+        if self.open {
+            self.open = false;
+            self.write(|w| w.write_all(&[INTERRUPTED_STATUS]));
+        }
+    }
+
+    fn write(&mut self, f: impl FnOnce(&mut Box<dyn Write>) -> io::Result<()>) {
+        if self.error.is_none() {
+            self.error = f(&mut self.writer).err();
+        }
+    }
+}
+
+/// 按顺序读取日志中的每一条记录
+pub struct TraceReader<R: Read> {
+    reader: R,
+    internal_max_size: SizeT,
+    leaf_max_size: SizeT
+}
+
+impl<R: Read> TraceReader<R> {
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        let mut version = [0u8; 1];
+        reader.read_exact(&mut version)?;
+        if &magic != TRACE_MAGIC || version[0] != TRACE_VERSION {
+            return Err(io::Error::new(ErrorKind::InvalidData, "not a B+ tree trace file"));
+        }
+
+        let internal_max_size = read_u32(&mut reader)? as SizeT;
+        let leaf_max_size = read_u32(&mut reader)? as SizeT;
+        Ok(TraceReader { reader, internal_max_size, leaf_max_size })
+    }
+
+    pub fn get_internal_max_size(&self) -> SizeT {
+        self.internal_max_size
+    }
+
+    pub fn get_leaf_max_size(&self) -> SizeT {
+        self.leaf_max_size
+    }
+}
+
+impl<R: Read> Iterator for TraceReader<R> {
+    type Item = io::Result<TraceRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        TraceOp::read_from(&mut self.reader).transpose()
+    }
+}
+
+/// 回放的结果，index为日志中第几条记录（从0开始）
+#[derive(Debug, PartialEq)]
+pub enum ReplayOutcome {
+    /// 所有记录的结果都与日志一致
+    Completed { op_count: usize },
+    /// 重新执行得到的结果与日志中记录的不同
+    Diverged { index: usize, expected: TraceOp, actual: TraceOp },
+    /// 操作执行完之后validate()失败
+    Corrupted { index: usize, op: TraceOp, corruption: TreeCorruption },
+    /// 操作执行时返回了错误
+    Failed { index: usize, op: TraceOp, error: BPlusTreeError },
+    /// 操作执行时panic
+    Panicked { index: usize, op: TraceOp, message: String },
+    /// 日志中返回了错误或者没有执行完的操作，回放时正常完成了
+    Unreproduced { index: usize, op: TraceOp, status: TraceStatus }
+}
+
+impl Display for ReplayOutcome {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReplayOutcome::Completed { op_count } => write!(f, "replayed {} operations without divergence", op_count),
+            ReplayOutcome::Diverged { index, expected, actual } => write!(f, "operation {} diverged: recorded {:?}, replayed {:?}", index, expected, actual),
+            ReplayOutcome::Corrupted { index, op, corruption } => write!(f, "operation {} {:?} corrupted the tree: {}", index, op, corruption),
+            ReplayOutcome::Failed { index, op, error } => write!(f, "operation {} {:?} failed: {}", index, op, error),
+            ReplayOutcome::Panicked { index, op, message } => write!(f, "operation {} {:?} panicked: {}", index, op, message),
+            ReplayOutcome::Unreproduced { index, op, status } => write!(f, "operation {} {:?} was recorded as {:?} but completed on replay", index, op, status)
+        }
+    }
+}
+
+/// 回放停止时的树，可以继续用print()、draw()等方法查看
+pub struct ReplayReport {
+    pub tree: BPlusTree,
+    pub outcome: ReplayOutcome
+}
+
+/// 在一棵新树上按顺序重新执行日志中的操作，遇到第一个结果不一致、结构错误、返回错误或者panic时停止，
+/// 日志中记录为返回了错误或者没有执行完的操作在回放时正常完成也会停止
+pub fn replay_trace(reader: impl Read) -> io::Result<ReplayReport> {
+    let trace_reader = TraceReader::new(reader)?;
+    let mut tree = BPlusTree::try_new(String::from("replay"), trace_reader.get_internal_max_size(), trace_reader.get_leaf_max_size())
+        .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+
+    let mut op_count = 0;
+    for (index, record) in trace_reader.enumerate() {
+        let TraceRecord { op, status } = record?;
+        let outcome = match panic::catch_unwind(AssertUnwindSafe(|| op.apply(&mut tree))) {
+            Ok(Ok(actual)) if status == TraceStatus::Completed && actual != op => Some(ReplayOutcome::Diverged { index, expected: op, actual }),
+            Ok(Err(error)) => Some(ReplayOutcome::Failed { index, op, error }),
+            Ok(Ok(_)) => match tree.validate() {
+                Err(corruption) => Some(ReplayOutcome::Corrupted { index, op, corruption }),
+                Ok(()) if status != TraceStatus::Completed => Some(ReplayOutcome::Unreproduced { index, op, status }),
+                Ok(()) => None
+            },
+            Err(payload) => {
+                let message = payload.downcast_ref::<&str>().map(|s| s.to_string())
+                    .or_else(|| payload.downcast_ref::<String>().cloned())
+                    .unwrap_or_default();
+                Some(ReplayOutcome::Panicked { index, op, message })
+            }
+        };
+
+        if let Some(outcome) = outcome {
+            return Ok(ReplayReport { tree, outcome });
+        }
+        op_count += 1;
+    }
+
+    Ok(ReplayReport { tree, outcome: ReplayOutcome::Completed { op_count } })
+}
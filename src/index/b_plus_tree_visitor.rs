@@ -0,0 +1,144 @@
+use std::collections::VecDeque;
+use crate::index::b_plus_tree::BPlusTree;
+use crate::page::b_plus_tree_page::{BPlusTreePage, BPlusTreePageType, RcPage, SizeT, ValueType};
+
+/// 遍历时交给visitor的只读节点快照
+///
+/// 内部节点下标为0的key不提供检索功能，所以keys只包含下标[1, size-1]的分隔关键字，
+/// 比child_ids少一个；叶子节点的keys和values一一对应
+///
+/// 损坏的树中为空的孩子指针不会出现在child_ids中，它左边的分隔关键字也一起跳过，
+/// 所以keys仍然比child_ids少一个
+#[derive(Clone, Debug, PartialEq)]
+pub struct PageView {
+    pub level: usize,
+    pub page_id: usize,
+    pub page_type: BPlusTreePageType,
+    pub max_size: SizeT,
+    pub min_size: SizeT,
+    pub keys: Vec<i32>,
+    pub values: Vec<i32>,
+    pub child_ids: Vec<usize>,
+    pub parent_id: Option<usize>,
+    pub next_id: Option<usize>
+}
+
+impl PageView {
+    fn new(page: &BPlusTreePage, level: usize) -> Self {
+        let mut view = PageView {
+            level,
+            page_id: page.get_page_id(),
+            page_type: page.get_page_type(),
+            max_size: page.get_max_size(),
+            min_size: page.get_min_size(),
+            keys: Vec::new(),
+            values: Vec::new(),
+            child_ids: Vec::new(),
+            parent_id: page.get_parent_page().map(|parent_page| parent_page.borrow().get_page_id()),
+            next_id: page.get_next_page().map(|next_page| next_page.borrow().get_page_id())
+        };
+
+        for i in 0..page.get_size() {
+            match page.value_at(i) {
+                ValueType::Page(None) => {}
+                ValueType::Page(Some(child_page)) => {
+                    if !view.child_ids.is_empty() {
+                        view.keys.push(page.key_at(i));
+                    }
+                    view.child_ids.push(child_page.borrow().get_page_id());
+                }
+                ValueType::Value(value) => {
+                    view.keys.push(page.key_at(i));
+                    view.values.push(value.unwrap_or_default());
+                }
+            }
+        }
+        view
+    }
+
+    pub fn is_leaf_page(&self) -> bool {
+        self.page_type == BPlusTreePageType::LeafPage
+    }
+
+    pub fn is_internal_page(&self) -> bool {
+        self.page_type == BPlusTreePageType::InternalPage
+    }
+
+    /// 与BPlusTreePage::get_size()一致：内部节点是孩子个数，叶子节点是key的个数
+    pub fn get_size(&self) -> SizeT {
+        if self.is_internal_page() {
+            self.child_ids.len()
+        } else {
+            self.keys.len()
+        }
+    }
+}
+
+/// BPlusTree::walk()访问节点的顺序，同一个节点的孩子总是按从左到右的顺序访问
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WalkOrder {
+    /// 先序深度优先：先访问节点本身，再依次访问每棵子树
+    DepthFirst,
+    /// 广度优先：按层从上到下访问，每一层从左到右
+    BreadthFirst
+}
+
+/// 进入节点时调用enter_page，离开节点时调用leave_page
+///
+/// 深度优先遍历时，leave_page在该节点的所有子树都访问完之后才调用；
+/// 广度优先遍历时，leave_page紧跟在同一个节点的enter_page之后调用
+pub trait PageVisitor {
+    fn enter_page(&mut self, page: &PageView);
+
+    fn leave_page(&mut self, _page: &PageView) {}
+}
+
+impl BPlusTree {
+    /// 从根节点开始按order访问每个节点，visitor只能看到节点的只读快照
+    pub fn walk(&self, order: WalkOrder, visitor: &mut impl PageVisitor) {
+        let root_page = match self.get_root_page() {
+            None => {
+                return;
+            }
+            Some(root_page) => {
+                root_page
+            }
+        };
+
+        match order {
+            WalkOrder::DepthFirst => {
+                Self::walk_page(root_page, 0, visitor);
+            }
+            WalkOrder::BreadthFirst => {
+                let mut queue = VecDeque::from([(root_page, 0)]);
+                while let Some((cur_page, level)) = queue.pop_front() {
+                    let view = PageView::new(&cur_page.borrow(), level);
+                    visitor.enter_page(&view);
+                    queue.extend(Self::child_pages(&cur_page).into_iter().map(|child_page| (child_page, level + 1)));
+                    visitor.leave_page(&view);
+                }
+            }
+        }
+    }
+
+    fn walk_page(cur_page: RcPage, level: usize, visitor: &mut impl PageVisitor) {
+        let view = PageView::new(&cur_page.borrow(), level);
+        visitor.enter_page(&view);
+
+        for child_page in Self::child_pages(&cur_page) {
+            Self::walk_page(child_page, level + 1, visitor);
+        }
+
+        visitor.leave_page(&view);
+    }
+
+    fn child_pages(cur_page: &RcPage) -> Vec<RcPage> {
+        let mut child_pages = Vec::new();
+        for i in 0..cur_page.borrow().get_size() {
+            if let ValueType::Page(Some(child_page)) = cur_page.borrow().value_at(i) {
+                child_pages.push(child_page);
+            }
+        }
+        child_pages
+    }
+}
@@ -0,0 +1,215 @@
+//! 暂存多个修改，再一次性应用到树上
+//!
+//! WriteBatch按key保存最后一次put或者delete，get()和iter()先看批次中的修改，再看树中的数据，
+//! 所以在提交之前就能读到自己暂存的修改。BPlusTree::apply()按key从小到大应用所有修改，
+//! 中途出错时按相反的顺序撤销已经应用的修改，树保持apply()之前的内容
+
+use std::collections::btree_map;
+use std::collections::BTreeMap;
+use std::iter::Peekable;
+use crate::index::b_plus_tree::{BPlusTree, BPlusTreeError, SearchPath, TreeCorruption};
+use crate::index::b_plus_tree_trace::TraceOp;
+use crate::iterator::b_plus_tree_iterator::BPlusTreeEntries;
+use crate::page::b_plus_tree_page::{RcPage, SizeT, ValueType};
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WriteBatch {
+    // value为None表示删除
+    writes_: BTreeMap<i32, Option<i32>>
+}
+
+impl WriteBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 写入key，与insert()不同，key已经存在时覆盖原来的value
+    pub fn put(&mut self, key: i32, value: i32) {
+        self.writes_.insert(key, Some(value));
+    }
+
+    pub fn delete(&mut self, key: i32) {
+        self.writes_.insert(key, None);
+    }
+
+    /// 暂存的修改涉及的key的个数，同一个key的多次修改只算一次
+    pub fn len(&self) -> SizeT {
+        self.writes_.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.writes_.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.writes_.clear();
+    }
+
+    /// 应用该批次之后tree中key的value
+    pub fn get(&self, tree: &BPlusTree, key: i32) -> Option<i32> {
+        match self.writes_.get(&key) {
+            Some(value) => *value,
+            None => tree.expect_ok(tree.lookup_value(key))
+        }
+    }
+
+    /// 按key从小到大遍历应用该批次之后tree中的键值对，tree不会被修改
+    pub fn iter<'a>(&'a self, tree: &'a BPlusTree) -> WriteBatchIter<'a> {
+        WriteBatchIter {
+            writes_: self.writes_.iter().peekable(),
+            entries_: tree.entries().peekable()
+        }
+    }
+}
+
+/// 合并WriteBatch和BPlusTree中的键值对，同一个key以WriteBatch中的修改为准
+pub struct WriteBatchIter<'a> {
+    writes_: Peekable<btree_map::Iter<'a, i32, Option<i32>>>,
+    entries_: Peekable<BPlusTreeEntries<'a>>
+}
+
+impl Iterator for WriteBatchIter<'_> {
+    type Item = (i32, i32);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let write_key = self.writes_.peek().map(|(key, _)| **key);
+            let entry_key = self.entries_.peek().map(|(key, _)| **key);
+            match (write_key, entry_key) {
+                (None, None) => {
+                    return None;
+                }
+                (None, Some(_)) => {
+                    return self.entries_.next().map(|(key, value)| (*key, *value));
+                }
+                (Some(write_key), Some(entry_key)) if entry_key < write_key => {
+                    return self.entries_.next().map(|(key, value)| (*key, *value));
+                }
+                (Some(write_key), entry_key) => {
+                    // 树中被批次覆盖或者删除的key直接跳过
+                    if entry_key == Some(write_key) {
+                        self.entries_.next();
+                    }
+                    if let Some((key, Some(value))) = self.writes_.next() {
+                        return Some((*key, *value));
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl BPlusTree {
+    /// 应用批次中的所有修改，要么全部成功，要么树保持原来的内容
+    pub fn apply(&mut self, batch: WriteBatch) {
+        let result = self.try_apply(batch);
+        self.expect_ok(result)
+    }
+
+    /// 与apply()相同，出错时撤销已经应用的修改后返回错误
+    pub fn try_apply(&mut self, batch: WriteBatch) -> Result<(), BPlusTreeError> {
+        // 每个已经应用的修改之前的value，用于撤销
+        let mut undo_log: Vec<(i32, Option<i32>)> = Vec::with_capacity(batch.len());
+        for (key, value) in batch.writes_ {
+            match self.write_entry(key, value) {
+                Ok(previous) => {
+                    undo_log.push((key, previous));
+                }
+                Err(e) => {
+                    for (key, previous) in undo_log.into_iter().rev() {
+                        // 撤销也失败时树已经无法恢复，返回最初的错误
+                        if self.write_entry(key, previous).is_err() {
+                            break;
+                        }
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl BPlusTree {
+    /// value为None时删除key，否则写入key并覆盖原来的value，返回原来的value
+    pub(crate) fn write_entry(&mut self, key: i32, value: Option<i32>) -> Result<Option<i32>, BPlusTreeError> {
+        self.rebuild_dirty_aggregates()?;
+        let leaf_path = self.find_leaf_path(key)?;
+        let previous = Self::leaf_value(&leaf_path, key)?;
+        self.write_leaf_entry(leaf_path, key, previous, value)?;
+        Ok(previous)
+    }
+
+    /// find_leaf_path()找到的叶子节点中key的value
+    pub(crate) fn leaf_value(leaf_path: &Option<(RcPage, SearchPath)>, key: i32) -> Result<Option<i32>, BPlusTreeError> {
+        match leaf_path {
+            None => Ok(None),
+            Some((leaf_page, _)) => {
+                let value = leaf_page.borrow().lookup(key);
+                match value {
+                    ValueType::Value(value) => Ok(value),
+                    ValueType::Page(_) => Err(TreeCorruption::InvalidPage { page_id: leaf_page.borrow().get_page_id() }.into())
+                }
+            }
+        }
+    }
+
+    /// 把key的value从previous改为value，None表示key不存在或者删除key，previous必须是从leaf_path的叶子节点中读到的value
+    ///
+    /// 直接在find_leaf_path()找到的叶子节点中修改，叶子节点需要分裂或者合并时才交给try_insert()/try_remove()重新查找
+    pub(crate) fn write_leaf_entry(&mut self, leaf_path: Option<(RcPage, SearchPath)>, key: i32, previous: Option<i32>,
+                                   value: Option<i32>) -> Result<(), BPlusTreeError> {
+        let (leaf_page, path) = match leaf_path {
+            None => {
+                if let Some(value) = value {
+                    self.try_insert(key, value)?;
+                }
+                return Ok(());
+            }
+            Some(leaf_path) => {
+                leaf_path
+            }
+        };
+
+        match (previous, value) {
+            (None, None) => Ok(()),
+            (Some(_), Some(value)) => {
+                let op = TraceOp::Update { key, value, previous };
+                self.traced(op, |tree| {
+                    let index = leaf_page.borrow().key_index(key);
+                    leaf_page.borrow_mut().set_value_at(index, ValueType::Value(Some(value)));
+                    tree.refresh_path(&path, 0)?;
+                    tree.check_invariants()
+                }, |_| op)
+            }
+            (None, Some(value)) => {
+                // 插入之后叶子节点达到leaf_max_size时需要分裂
+                if leaf_page.borrow().get_size() + 1 >= self.get_leaf_max_size() {
+                    self.try_insert(key, value)?;
+                    return Ok(());
+                }
+                let op = TraceOp::Insert { key, value, inserted: true };
+                self.traced(op, |tree| {
+                    leaf_page.borrow_mut().insert(key, value);
+                    tree.set_len(tree.len() + 1);
+                    tree.refresh_path(&path, 1)?;
+                    tree.check_invariants()
+                }, |_| op)
+            }
+            (Some(_), None) => {
+                // 删除之后叶子节点小于get_min_size()时需要合并或者重新分配，根节点变为空时需要调整
+                if leaf_page.borrow().is_root_page() || leaf_page.borrow().get_size() <= leaf_page.borrow().get_min_size() {
+                    self.try_remove(key)?;
+                    return Ok(());
+                }
+                let op = TraceOp::Remove { key, removed: true };
+                self.traced(op, |tree| {
+                    leaf_page.borrow_mut().remove_and_delete_record(key);
+                    tree.set_len(tree.len() - 1);
+                    tree.refresh_path(&path, -1)?;
+                    tree.check_invariants()
+                }, |_| op)
+            }
+        }
+    }
+}
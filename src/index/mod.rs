@@ -1 +1,1371 @@
-pub mod b_plus_tree;
\ No newline at end of file
+pub mod b_plus_tree;
+pub mod b_plus_tree_aggregate;
+pub mod b_plus_tree_batch;
+pub mod b_plus_tree_changes;
+pub mod b_plus_tree_collection;
+pub mod b_plus_tree_conditional;
+pub mod b_plus_tree_explain;
+pub mod b_plus_tree_merge;
+pub mod b_plus_tree_navigation;
+pub mod b_plus_tree_rank;
+pub mod b_plus_tree_recorder;
+pub mod b_plus_tree_remove;
+pub mod b_plus_tree_render;
+pub mod b_plus_tree_split;
+pub mod b_plus_tree_stats;
+pub mod b_plus_tree_trace;
+pub mod b_plus_tree_visitor;
+pub mod b_plus_tree_write_batch;
+#[cfg(test)]
+mod b_plus_tree_model_test;
+
+
+#[cfg(test)]
+mod tests {
+    use crate::index::b_plus_tree::{BPlusTree, BPlusTreeError, TreeCorruption};
+    use crate::index::b_plus_tree_recorder::{write_frames, FrameFormat, StructuralEvent};
+    use crate::index::b_plus_tree_render::{DotColorScheme, DotOptions};
+    use crate::index::b_plus_tree_trace::{replay_trace, ReplayOutcome, TraceOp, TraceReader};
+    use crate::index::b_plus_tree_visitor::{PageView, PageVisitor, WalkOrder};
+    use crate::page::b_plus_tree_page::{BPlusTreePage, BPlusTreePageType, ValueType};
+    use std::ops::Bound;
+
+    #[test]
+    fn b_plus_tree_validate_test() {
+        for (internal_max_size, leaf_max_size) in [(3, 3), (3, 4), (4, 3), (5, 5), (4, 128), (128, 128)] {
+            let mut tree = BPlusTree::new(String::from("tree1"), internal_max_size, leaf_max_size);
+            tree.set_check_invariants(true);
+
+            for i in 0..500 {
+                tree.insert((i * 37) % 500, i);
+            }
+            assert_eq!(Ok(()), tree.validate());
+
+            for i in 0..500 {
+                tree.remove((i * 53) % 500);
+            }
+            assert_eq!(Ok(()), tree.validate());
+            assert!(tree.is_empty());
+        }
+    }
+
+    #[test]
+    fn b_plus_tree_stats_test() {
+        let mut tree = BPlusTree::new(String::from("tree1"), 3, 3);
+        assert_eq!(0, tree.stats().height);
+
+        for i in 0..=10 {
+            tree.insert(i, i);
+        }
+
+        let stats = tree.stats();
+        assert_eq!(11, stats.entry_count);
+        assert_eq!(stats.leaf_page_count, stats.levels.last().unwrap().page_count);
+        assert_eq!(stats.height, stats.levels.len());
+        assert_eq!(1, stats.levels[0].page_count);
+        assert_eq!(stats.internal_page_count, stats.levels[..stats.height - 1].iter().map(|level| level.page_count).sum());
+        // 每次split产生一个新节点，每次树长高产生一个新的根节点
+        assert_eq!(stats.internal_page_count + stats.leaf_page_count - 1, stats.split_count + stats.height - 1);
+        assert_eq!(0, stats.merge_count);
+        for level in &stats.levels[1..] {
+            assert!(level.min_fill_factor >= 0.5 && level.min_fill_factor <= level.average_fill_factor);
+        }
+
+        for i in 0..=5 {
+            tree.remove(i);
+        }
+
+        let stats = tree.stats();
+        assert_eq!(5, stats.entry_count);
+        assert!(stats.merge_count > 0);
+        assert!(stats.redistribute_count > 0);
+    }
+
+    #[test]
+    fn b_plus_tree_validate_corruption_test() {
+        let mut tree = BPlusTree::new(String::from("tree1"), 3, 3);
+        for i in 0..10 {
+            tree.insert(i, i);
+        }
+        assert_eq!(Ok(()), tree.validate());
+
+        let mut cur_page = tree.get_root_page().unwrap();
+        while cur_page.borrow().is_internal_page() {
+            let child_page = match cur_page.borrow().value_at(0) {
+                ValueType::Page(child_page) => child_page.unwrap(),
+                ValueType::Value(_) => unreachable!()
+            };
+            cur_page = child_page;
+        }
+        let page_id = cur_page.borrow().get_page_id();
+        cur_page.borrow_mut().set_key_at(0, 100);
+
+        assert!(matches!(tree.validate(), Err(TreeCorruption::KeyOutOfRange { page_id: id, key: 100, .. }) if id == page_id));
+    }
+
+    #[test]
+    fn b_plus_tree_render_dot_test() {
+        let mut tree = BPlusTree::new(String::from("tree1"), 3, 3);
+        assert_eq!("digraph G {\n}\n", tree.to_dot());
+
+        for i in 0..=10 {
+            tree.insert(i, i);
+        }
+
+        let dot = tree.to_dot();
+        assert!(dot.starts_with("digraph G {") && dot.ends_with("}\n"));
+        assert!(dot.contains("color=\"green\"") && dot.contains("color=\"pink\""));
+        assert!(dot.contains("max_size=3"));
+        assert!(dot.contains("rank=same LEAF_"));
+
+        let mut buf = Vec::new();
+        tree.render_dot(&mut buf).unwrap();
+        assert_eq!(dot, String::from_utf8(buf).unwrap());
+
+        let options = DotOptions {
+            title: Some(String::from("tree \"1\"")),
+            color_scheme: DotColorScheme {
+                internal_color: String::from("blue"),
+                leaf_color: String::from("#00ff00"),
+                highlight_color: String::from("red")
+            },
+            show_sizes: false,
+            show_next_links: false,
+            highlighted_page_ids: vec![tree.get_root_page().unwrap().borrow().get_page_id()]
+        };
+        let dot = tree.to_dot_with_options(&options);
+        assert!(dot.contains("label=\"tree \\\"1\\\"\";"));
+        assert!(dot.contains("color=\"#00ff00\"") && dot.contains("color=\"blue\""));
+        assert_eq!(1, dot.matches("color=\"red\"").count());
+        assert!(!dot.contains("max_size="));
+        assert!(!dot.contains("rank=same LEAF_"));
+    }
+
+    #[test]
+    fn b_plus_tree_visualize_test() {
+        let mut tree = BPlusTree::new(String::from("tree\"1\""), 3, 3);
+        assert_eq!("flowchart TD\n", tree.to_mermaid());
+        assert_eq!("{\"index_name\":\"tree\\\"1\\\"\",\"root\":null}", tree.to_json());
+        assert_eq!("", tree.to_ascii());
+
+        for i in 0..=10 {
+            tree.insert(i, i * 10);
+        }
+        let stats = tree.stats();
+        let page_count = stats.internal_page_count + stats.leaf_page_count;
+
+        let mermaid = tree.to_mermaid();
+        assert!(mermaid.starts_with("flowchart TD\n"));
+        // 除根节点外每个节点都有一条父节点指向它的边，每个叶子节点除最后一个外都有一条next边
+        assert_eq!(page_count - 1, mermaid.matches(" --> ").count());
+        assert_eq!(stats.leaf_page_count - 1, mermaid.matches(" -.-> ").count());
+
+        let json = tree.to_json();
+        assert_eq!(page_count, json.matches("\"page_id\"").count());
+        assert_eq!(stats.leaf_page_count, json.matches("\"type\":\"leaf\"").count());
+        assert_eq!(json.matches('{').count(), json.matches('}').count());
+        assert_eq!(json.matches('[').count(), json.matches(']').count());
+        assert!(json.contains("\"values\":[0, 10]") || json.contains("\"values\":[0]"));
+        assert_eq!(1, json.matches("\"next\":null").count());
+
+        let ascii = tree.to_ascii();
+        assert_eq!(stats.height * 3, ascii.lines().count());
+        assert_eq!(stats.leaf_page_count - 1, ascii.matches("-->").count());
+        assert_eq!(page_count, ascii.matches("| P").count());
+    }
+
+    #[test]
+    fn b_plus_tree_recording_test() {
+        let mut tree = BPlusTree::new(String::from("tree1"), 3, 3);
+        assert!(tree.stop_recording().is_empty());
+
+        tree.start_recording(FrameFormat::Dot);
+        for i in 0..=10 {
+            tree.insert(i, i);
+        }
+        for i in 0..=10 {
+            tree.remove(i);
+        }
+        let frames = tree.stop_recording();
+
+        for (i, frame) in frames.iter().enumerate() {
+            assert_eq!(i + 1, frame.sequence);
+            assert!(frame.content.contains(format!("label=\"#{} {}\";", frame.sequence, frame.event.name()).as_str()));
+            assert_eq!(frame.changed_page_ids.len(), frame.content.matches("color=\"red\"").count());
+        }
+        assert_eq!(StructuralEvent::LeafSplit, frames[0].event);
+        assert_eq!(StructuralEvent::AdjustRoot, frames.last().unwrap().event);
+        for event in [StructuralEvent::InsertIntoParent, StructuralEvent::Coalesce, StructuralEvent::Redistribute] {
+            assert!(frames.iter().any(|frame| frame.event == event));
+        }
+        let stats = tree.stats();
+        assert_eq!(stats.split_count, frames.iter().filter(|frame| frame.event == StructuralEvent::LeafSplit || frame.event == StructuralEvent::InsertIntoParent).count());
+
+        tree.start_recording(FrameFormat::Json);
+        for i in 0..3 {
+            tree.insert(i, i);
+        }
+        let frames = tree.stop_recording();
+        assert_eq!(1, frames.len());
+        assert!(frames[0].content.starts_with("{\"sequence\":1,\"event\":\"leaf_split\",\"changed\":["));
+        assert_eq!("frame_0001_leaf_split.json", frames[0].file_name());
+
+        let dir = std::env::temp_dir().join(format!("b_plus_tree_frames_{}", std::process::id()));
+        write_frames(&frames, &dir).unwrap();
+        assert!(dir.join("frame_0001_leaf_split.json").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn b_plus_tree_explain_get_test() {
+        let mut tree = BPlusTree::new(String::from("tree1"), 3, 3);
+        assert_eq!(None, tree.explain_get(1).result);
+        assert!(tree.explain_get(1).steps.is_empty());
+
+        for i in 0..=10 {
+            tree.insert(i * 2, i);
+        }
+        let height = tree.stats().height;
+
+        let explain = tree.explain_get(8);
+        assert_eq!(Some(4), explain.result);
+        assert_eq!(height, explain.steps.len());
+        assert_eq!(tree.get_root_page().unwrap().borrow().get_page_id(), explain.steps[0].page_id);
+        for pair in explain.steps.windows(2) {
+            assert_eq!(Some(pair[1].page_id), pair[0].child_page_id);
+            assert!(!pair[0].compared_keys.is_empty());
+        }
+        let leaf_step = explain.steps.last().unwrap();
+        assert!(leaf_step.compared_keys.contains(&8));
+        assert!(leaf_step.chosen_index.is_some() && leaf_step.child_page_id.is_none());
+
+        let explain = tree.explain_get(9);
+        assert_eq!(None, explain.result);
+        assert_eq!(None, explain.steps.last().unwrap().chosen_index);
+        assert!(explain.to_string().ends_with("not found\nresult: None\n"));
+
+        let dot = tree.to_dot_lookup(8);
+        assert_eq!(height, dot.matches("color=\"red\"").count());
+    }
+
+    #[derive(Default)]
+    struct RecordingVisitor {
+        entered: Vec<PageView>,
+        // 正数为enter_page的page_id + 1，负数为leave_page
+        events: Vec<i64>
+    }
+
+    impl PageVisitor for RecordingVisitor {
+        fn enter_page(&mut self, page: &PageView) {
+            self.entered.push(page.clone());
+            self.events.push(page.page_id as i64 + 1);
+        }
+
+        fn leave_page(&mut self, page: &PageView) {
+            self.events.push(-(page.page_id as i64 + 1));
+        }
+    }
+
+    #[test]
+    fn b_plus_tree_walk_test() {
+        let mut tree = BPlusTree::new(String::from("tree1"), 3, 3);
+        let mut visitor = RecordingVisitor::default();
+        tree.walk(WalkOrder::DepthFirst, &mut visitor);
+        assert!(visitor.entered.is_empty());
+
+        for i in 0..=20 {
+            tree.insert(i, i);
+        }
+        let stats = tree.stats();
+        let root_page_id = tree.get_root_page().unwrap().borrow().get_page_id();
+
+        let mut dfs = RecordingVisitor::default();
+        tree.walk(WalkOrder::DepthFirst, &mut dfs);
+        let mut bfs = RecordingVisitor::default();
+        tree.walk(WalkOrder::BreadthFirst, &mut bfs);
+
+        for visitor in [&dfs, &bfs] {
+            assert_eq!(stats.internal_page_count + stats.leaf_page_count, visitor.entered.len());
+            assert_eq!(root_page_id, visitor.entered[0].page_id);
+            assert_eq!(None, visitor.entered[0].parent_id);
+            for page in &visitor.entered {
+                assert_eq!(page.is_internal_page(), page.keys.len() + 1 == page.child_ids.len());
+                assert_eq!(page.is_leaf_page(), page.level + 1 == stats.height);
+            }
+        }
+
+        // 深度优先时enter/leave成对嵌套
+        let mut stack = Vec::new();
+        for event in &dfs.events {
+            if *event > 0 {
+                stack.push(*event);
+            } else {
+                assert_eq!(Some(-*event), stack.pop());
+            }
+        }
+        assert!(stack.is_empty());
+
+        // 广度优先时按层访问，最后一层从左到右正好是叶子节点的next链表
+        assert!(bfs.entered.windows(2).all(|pair| pair[0].level <= pair[1].level));
+        let leaves: Vec<&PageView> = bfs.entered.iter().filter(|page| page.is_leaf_page()).collect();
+        for pair in leaves.windows(2) {
+            assert_eq!(Some(pair[1].page_id), pair[0].next_id);
+        }
+        let keys: Vec<i32> = leaves.iter().flat_map(|page| page.keys.clone()).collect();
+        assert_eq!((0..=20).collect::<Vec<i32>>(), keys);
+    }
+
+    #[test]
+    fn b_plus_tree_trace_test() {
+        let path = std::env::temp_dir().join(format!("b_plus_tree_trace_{}.bin", std::process::id()));
+        let mut tree = BPlusTree::new(String::from("tree1"), 3, 4);
+        tree.start_trace(std::io::BufWriter::new(std::fs::File::create(&path).unwrap())).unwrap();
+        for i in 0..50 {
+            tree.insert(i, i * 10);
+        }
+        tree.insert(7, 0);
+        for i in (0..60).step_by(2) {
+            tree.remove(i);
+        }
+        for i in 0..50 {
+            tree.get_value(i);
+        }
+        tree.stop_trace().unwrap();
+        // 停止之后的操作不再记录
+        tree.insert(100, 100);
+
+        let mut bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let reader = TraceReader::new(bytes.as_slice()).unwrap();
+        assert_eq!((3, 4), (reader.get_internal_max_size(), reader.get_leaf_max_size()));
+        let ops: Vec<TraceOp> = reader.map(|record| record.unwrap().op).collect();
+        assert_eq!(131, ops.len());
+        assert_eq!(TraceOp::Insert { key: 7, value: 0, inserted: false }, ops[50]);
+        assert_eq!(TraceOp::Remove { key: 58, removed: false }, ops[80]);
+        assert_eq!(TraceOp::Get { key: 0, value: None }, ops[81]);
+        assert_eq!(TraceOp::Get { key: 49, value: Some(490) }, ops[130]);
+
+        let report = replay_trace(bytes.as_slice()).unwrap();
+        assert_eq!(ReplayOutcome::Completed { op_count: 131 }, report.outcome);
+        assert_eq!(tree.iter().filter(|value| *value != 100).collect::<Vec<i32>>(), report.tree.iter().collect::<Vec<i32>>());
+
+        // 把最后一条get记录的结果改掉，回放应该停在这一条
+        let len = bytes.len();
+        bytes[len - 4..].copy_from_slice(&491i32.to_le_bytes());
+        let report = replay_trace(bytes.as_slice()).unwrap();
+        assert_eq!(ReplayOutcome::Diverged {
+            index: 130,
+            expected: TraceOp::Get { key: 49, value: Some(491) },
+            actual: TraceOp::Get { key: 49, value: Some(490) }
+        }, report.outcome);
+
+        // 截断的记录和不是日志的文件都返回错误
+        assert!(replay_trace(&bytes[..len - 1]).is_err());
+        assert!(replay_trace(&b"digraph G {}"[..]).is_err());
+    }
+
+    #[test]
+    fn b_plus_tree_error_test() {
+        assert_eq!(Some(BPlusTreeError::InvalidInternalMaxSize(2)), BPlusTree::try_new(String::from("tree1"), 2, 3).err());
+        assert_eq!(Some(BPlusTreeError::InvalidLeafMaxSize(0)), BPlusTree::try_new(String::from("tree1"), 3, 0).err());
+        assert!(std::panic::catch_unwind(|| BPlusTree::new(String::from("tree1"), 3, 1)).is_err());
+
+        // 打开不变量检查后，结构错误由try_insert()返回
+        let mut tree = BPlusTree::try_new(String::from("tree1"), 3, 3).unwrap();
+        for i in 0..10 {
+            assert_eq!(Ok(true), tree.try_insert(i, i));
+        }
+        assert_eq!(Ok(false), tree.try_insert(0, 1));
+        assert_eq!(Ok(Some(5)), tree.try_get_value(5));
+        assert_eq!(Ok(true), tree.try_remove(5));
+        assert_eq!(Ok(false), tree.try_remove(5));
+
+        tree.set_check_invariants(true);
+        let root_page = tree.get_root_page().unwrap();
+        let root_page_id = root_page.borrow().get_page_id();
+        root_page.borrow_mut().set_key_at(1, -100);
+        assert!(matches!(tree.try_insert(100, 100), Err(BPlusTreeError::Corrupted(TreeCorruption::KeyOutOfRange { .. }))));
+
+        // 根节点的孩子全部丢失，查找时返回错误，不返回Result的方法panic
+        while root_page.borrow().get_size() > 0 {
+            root_page.borrow_mut().remove(0);
+        }
+        assert_eq!(Err(BPlusTreeError::Corrupted(TreeCorruption::InvalidPage { page_id: root_page_id })), tree.try_get_value(1));
+        assert!(matches!(tree.try_remove(1), Err(BPlusTreeError::Corrupted(_))));
+        assert!(std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| tree.get_value(1))).is_err());
+        assert!(format!("{:?}", tree).contains("index_name: \"tree1\""));
+    }
+
+    #[test]
+    fn b_plus_tree_collection_test() {
+        use std::collections::hash_map::DefaultHasher;
+        use std::collections::BTreeMap;
+        use std::hash::{Hash, Hasher};
+
+        let pairs: Vec<(i32, i32)> = (0..200).map(|i| ((i * 37) % 101, i)).collect();
+        let mut tree: BPlusTree = pairs.iter().copied().collect();
+        let mut map: BTreeMap<i32, i32> = pairs.iter().copied().collect();
+        assert_eq!(map.len(), tree.len());
+        assert_eq!(map.clone().into_iter().collect::<Vec<(i32, i32)>>(), tree.clone().into_iter().collect::<Vec<(i32, i32)>>());
+        assert_eq!(map[&5], tree[&5]);
+        assert_eq!(None, tree.get(&101));
+        assert!(tree.contains_key(&100) && !tree.contains_key(&-1));
+        assert!(std::panic::catch_unwind(|| BPlusTree::default()[&1]).is_err());
+
+        let mut small = BPlusTree::new(String::from("tree1"), 3, 3);
+        small.extend(&tree);
+        small.extend([(1000, 1), (5, -5)]);
+        map.extend([(1000, 1), (5, -5)]);
+        assert_eq!(map.len(), small.len());
+        assert_eq!(map.iter().collect::<Vec<(&i32, &i32)>>(), (&small).into_iter().collect::<Vec<(&i32, &i32)>>());
+
+        // 相等和哈希只看键值对，不看节点大小
+        small.remove(1000);
+        small.insert(5, 0);
+        tree.extend([(5, -5)]);
+        assert!(small == tree);
+        let hash = |tree: &BPlusTree| {
+            let mut hasher = DefaultHasher::new();
+            tree.hash(&mut hasher);
+            hasher.finish()
+        };
+        assert_eq!(hash(&small), hash(&tree));
+        let cloned = small.clone();
+        assert_eq!(small, cloned);
+
+        for (key, value) in &mut small {
+            *value = *key * 2;
+        }
+        assert!(small.entries().all(|(key, value)| *value == *key * 2));
+        assert_ne!(small, cloned);
+        assert_ne!(hash(&small), hash(&cloned));
+
+        small.clear();
+        assert!(small.is_empty());
+        assert_eq!(0, small.len());
+        assert_eq!(BPlusTree::default(), small);
+        assert!(small.insert(1, 1));
+        assert_eq!(1, small.len());
+    }
+
+    #[test]
+    fn b_plus_tree_navigation_test() {
+        use std::collections::BTreeMap;
+
+        let mut tree = BPlusTree::new(String::from("tree1"), 3, 3);
+        assert_eq!(None, tree.first_key_value());
+        assert_eq!(None, tree.last_key_value());
+        assert_eq!(None, tree.floor(0));
+        assert_eq!(None, tree.pop_first());
+
+        let mut map = BTreeMap::new();
+        for i in 0..60 {
+            let key = (i * 37) % 61 * 3;
+            tree.insert(key, i);
+            map.insert(key, i);
+        }
+        // 删除之后父节点中的分隔关键字可能已经不在树中，floor需要回到左边的子树
+        for i in (0..60).step_by(4) {
+            let key = (i * 37) % 61 * 3;
+            tree.remove(key);
+            map.remove(&key);
+        }
+
+        assert_eq!(map.first_key_value(), tree.first_key_value());
+        assert_eq!(map.last_key_value(), tree.last_key_value());
+        for key in -2..190 {
+            assert_eq!(map.range(..=key).next_back(), tree.floor(key), "floor({})", key);
+            assert_eq!(map.range(key..).next(), tree.ceiling(key), "ceiling({})", key);
+            assert_eq!(map.range(..key).next_back(), tree.predecessor(key), "predecessor({})", key);
+            assert_eq!(map.range(key + 1..).next(), tree.successor(key), "successor({})", key);
+        }
+        assert_eq!(None, tree.successor(i32::MAX));
+        assert_eq!(None, tree.predecessor(i32::MIN));
+
+        while let Some(first) = map.pop_first() {
+            assert_eq!(Some(first), tree.pop_first());
+            assert_eq!(map.pop_last(), tree.pop_last());
+            assert_eq!(Ok(()), tree.validate());
+        }
+        assert!(tree.is_empty());
+        assert_eq!(None, tree.pop_last());
+    }
+
+    #[test]
+    fn b_plus_tree_rank_test() {
+        use std::collections::BTreeMap;
+
+        let mut tree = BPlusTree::new(String::from("tree1"), 3, 4);
+        assert_eq!(0, tree.rank(0));
+        assert_eq!(None, tree.select(0));
+        assert_eq!(0, tree.count_range(..));
+
+        let mut map = BTreeMap::new();
+        for i in 0..150 {
+            let key = (i * 53) % 151 * 2;
+            tree.insert(key, i);
+            map.insert(key, i);
+        }
+        for i in (0..150).step_by(3) {
+            let key = (i * 53) % 151 * 2;
+            tree.remove(key);
+            map.remove(&key);
+        }
+        assert_eq!(Ok(()), tree.validate());
+
+        let keys: Vec<i32> = map.keys().copied().collect();
+        for (index, (key, value)) in map.iter().enumerate() {
+            assert_eq!(index, tree.rank(*key));
+            assert_eq!(index + 1, tree.rank(*key + 1));
+            assert_eq!(Some((key, value)), tree.select(index));
+        }
+        assert_eq!(None, tree.select(keys.len()));
+        for a in (-3..310).step_by(7) {
+            for b in (a..310).step_by(11) {
+                assert_eq!(map.range(a..b).count(), tree.count_range(a..b));
+                assert_eq!(map.range(a..=b).count(), tree.count_range(a..=b));
+                assert_eq!(map.range((Bound::Excluded(a), Bound::Included(b))).count(), tree.count_range((Bound::Excluded(a), Bound::Included(b))));
+            }
+            assert_eq!(map.range(a..).count(), tree.count_range(a..));
+            assert_eq!(map.range(..a).count(), tree.count_range(..a));
+        }
+        assert_eq!(keys.len(), tree.count_range(..));
+        assert_eq!(0, tree.count_range((Bound::Included(10), Bound::Excluded(5))));
+    }
+
+    #[test]
+    fn b_plus_tree_aggregate_test() {
+        use crate::index::b_plus_tree_aggregate::{Max, Min, Monoid, Sum};
+        use std::collections::BTreeMap;
+
+        fn check(tree: &BPlusTree, map: &BTreeMap<i32, i32>, monoid: &dyn Monoid) {
+            let fold = |range: (Bound<i32>, Bound<i32>)| {
+                map.range(range).fold(monoid.identity(), |acc, (_, value)| monoid.combine(acc, monoid.lift(*value)))
+            };
+            for a in (-5..320).step_by(13) {
+                for b in (a..320).step_by(17) {
+                    assert_eq!(Some(fold((Bound::Included(a), Bound::Excluded(b)))), tree.aggregate(a..b));
+                    assert_eq!(Some(fold((Bound::Excluded(a), Bound::Included(b)))), tree.aggregate((Bound::Excluded(a), Bound::Included(b))));
+                }
+                assert_eq!(Some(fold((Bound::Unbounded, Bound::Excluded(a)))), tree.aggregate(..a));
+                assert_eq!(Some(fold((Bound::Included(a), Bound::Unbounded))), tree.aggregate(a..));
+            }
+            assert_eq!(Some(fold((Bound::Unbounded, Bound::Unbounded))), tree.aggregate(..));
+        }
+
+        let mut tree = BPlusTree::new(String::from("tree1"), 3, 4);
+        assert_eq!(None, tree.aggregate(..));
+        tree.set_aggregator(Sum);
+        assert_eq!(Some(0), tree.aggregate(..));
+
+        let mut map = BTreeMap::new();
+        for i in 0..150 {
+            let key = (i * 53) % 151 * 2;
+            tree.insert(key, i * 7 - 300);
+            map.insert(key, i * 7 - 300);
+        }
+        assert_eq!(Ok(()), tree.validate());
+        check(&tree, &map, &Sum);
+
+        // 先插入再设置的聚合函数也要重新计算所有缓存的聚合值
+        tree.set_aggregator(Min);
+        assert_eq!(Ok(()), tree.validate());
+        check(&tree, &map, &Min);
+
+        tree.set_aggregator(Max);
+        for i in (0..150).step_by(3) {
+            let key = (i * 53) % 151 * 2;
+            tree.remove(key);
+            map.remove(&key);
+        }
+        assert_eq!(Ok(()), tree.validate());
+        check(&tree, &map, &Max);
+
+        // entries_mut()之后重新计算访问过的叶子节点所在的子树，下一次修改时沿着这些路径更新缓存的聚合值
+        tree.set_aggregator(Sum);
+        for (_, value) in tree.entries_mut() {
+            *value *= 2;
+        }
+        for value in map.values_mut() {
+            *value *= 2;
+        }
+        check(&tree, &map, &Sum);
+        tree.insert(1, 1000);
+        map.insert(1, 1000);
+        assert_eq!(Ok(()), tree.validate());
+        check(&tree, &map, &Sum);
+
+        let cloned = tree.clone();
+        assert_eq!(Ok(()), cloned.validate());
+        check(&cloned, &map, &Sum);
+
+        // 只修改前几个value，其余叶子节点没有被访问
+        for (key, value) in tree.entries_mut().take(5) {
+            *value += 1;
+            *map.get_mut(key).unwrap() += 1;
+        }
+        check(&tree, &map, &Sum);
+        tree.remove(1);
+        map.remove(&1);
+        assert_eq!(Ok(()), tree.validate());
+        check(&tree, &map, &Sum);
+
+        // 没有修改树时连续两次entries_mut()
+        for (key, value) in tree.entries_mut().skip(40).take(30) {
+            *value -= 7;
+            *map.get_mut(key).unwrap() -= 7;
+        }
+        for (key, value) in tree.entries_mut().skip(60) {
+            *value *= 3;
+            *map.get_mut(key).unwrap() *= 3;
+        }
+        check(&tree, &map, &Sum);
+        tree.insert(-10, 0);
+        map.insert(-10, 0);
+        assert_eq!(Ok(()), tree.validate());
+        check(&tree, &map, &Sum);
+
+        tree.remove_aggregator();
+        assert_eq!(None, tree.aggregate(..));
+        tree.insert(3, 3);
+        assert_eq!(Ok(()), tree.validate());
+    }
+
+    #[test]
+    fn b_plus_tree_split_append_merge_test() {
+        use crate::index::b_plus_tree_aggregate::Sum;
+        use std::collections::BTreeMap;
+
+        for (internal_max_size, leaf_max_size) in [(3, 3), (3, 4), (4, 3), (5, 5), (4, 16)] {
+            let mut tree = BPlusTree::new(String::from("tree1"), internal_max_size, leaf_max_size);
+            tree.set_aggregator(Sum);
+            let mut map = BTreeMap::new();
+            for i in 0..200 {
+                let key = (i * 53) % 211 * 2;
+                tree.insert(key, i);
+                map.insert(key, i);
+            }
+
+            for split_key in (-3..430).step_by(9) {
+                let mut left = tree.clone();
+                let mut left_map = map.clone();
+                let mut right = left.split_off(split_key);
+                let right_map = left_map.split_off(&split_key);
+                assert_eq!(Ok(()), left.validate());
+                assert_eq!(Ok(()), right.validate());
+                assert!(left.entries().map(|(k, v)| (*k, *v)).eq(left_map.clone()));
+                assert!(right.entries().map(|(k, v)| (*k, *v)).eq(right_map.clone()));
+                assert_eq!(Some(left_map.values().map(|v| *v as i64).sum()), left.aggregate(..));
+
+                // 拆分之后的两棵树仍然可以正常修改
+                left.insert(-1, 7);
+                right.insert(1000, 7);
+                assert_eq!(Ok(()), left.validate());
+                assert_eq!(Ok(()), right.validate());
+                left.remove(-1);
+                right.remove(1000);
+
+                left.append(&mut right);
+                assert!(right.is_empty());
+                assert_eq!(Ok(()), left.validate());
+                assert_eq!(tree, left);
+            }
+
+            // 高度不同的两棵树，较矮的树分别在左边和右边
+            for small_len in [1, 2, 5, 17] {
+                let mut small = BPlusTree::new(String::from("small"), internal_max_size, leaf_max_size);
+                for key in 0..small_len {
+                    small.insert(key, key);
+                }
+                let mut large = BPlusTree::new(String::from("large"), internal_max_size, leaf_max_size);
+                for key in 100..400 {
+                    large.insert(key, key);
+                }
+                let mut expected: BTreeMap<i32, i32> = small.entries().chain(large.entries()).map(|(k, v)| (*k, *v)).collect();
+
+                let mut left = small.clone();
+                left.append(&mut large.clone());
+                assert_eq!(Ok(()), left.validate());
+                assert!(left.entries().map(|(k, v)| (*k, *v)).eq(expected.clone()));
+
+                let mut right = large.clone();
+                right.merge(&mut small);
+                assert!(small.is_empty());
+                assert_eq!(Ok(()), right.validate());
+                assert_eq!(left, right);
+
+                // key有重叠时使用other中的value
+                let mut other = BPlusTree::new(String::from("other"), internal_max_size, leaf_max_size);
+                for key in (50..150).step_by(3) {
+                    other.insert(key, -key);
+                    expected.insert(key, -key);
+                }
+                right.merge(&mut other);
+                assert!(other.is_empty());
+                assert_eq!(Ok(()), right.validate());
+                assert!(right.entries().map(|(k, v)| (*k, *v)).eq(expected));
+            }
+        }
+    }
+
+    #[test]
+    fn b_plus_tree_remove_range_test() {
+        use crate::index::b_plus_tree_aggregate::Sum;
+        use std::collections::BTreeMap;
+
+        for (internal_max_size, leaf_max_size) in [(3, 3), (3, 4), (5, 5), (4, 16)] {
+            let mut tree = BPlusTree::new(String::from("tree1"), internal_max_size, leaf_max_size);
+            tree.set_aggregator(Sum);
+            let mut map = BTreeMap::new();
+            for i in 0..200 {
+                let key = (i * 53) % 211 * 2;
+                tree.insert(key, i);
+                map.insert(key, i);
+            }
+
+            for a in (-5..430).step_by(23) {
+                for b in (a..440).step_by(31) {
+                    let mut cut = tree.clone();
+                    let mut cut_map = map.clone();
+                    let expected: Vec<(i32, i32)> = cut_map.range(a..=b).map(|(k, v)| (*k, *v)).collect();
+                    cut_map.retain(|key, _| *key < a || *key > b);
+                    assert_eq!(expected.len(), cut.remove_range(a..=b));
+                    assert_eq!(Ok(()), cut.validate());
+                    assert!(cut.entries().map(|(k, v)| (*k, *v)).eq(cut_map.clone()));
+                    assert_eq!(Some(cut_map.values().map(|v| *v as i64).sum()), cut.aggregate(..));
+
+                    let mut drained = tree.clone();
+                    assert_eq!(expected, drained.drain(a..=b).collect::<Vec<(i32, i32)>>());
+                    assert_eq!(cut, drained);
+                }
+            }
+            assert_eq!(0, tree.clone().remove_range((Bound::Excluded(i32::MAX), Bound::Unbounded)));
+            assert_eq!(0, tree.clone().remove_range((Bound::Included(10), Bound::Excluded(10))));
+            let mut all = tree.clone();
+            assert_eq!(map.len(), all.remove_range(..));
+            assert!(all.is_empty());
+            assert_eq!(Ok(()), all.validate());
+
+            let mut retained = tree.clone();
+            retained.retain(|key, value| {
+                *value += 1;
+                key % 3 != 0 && !(100..200).contains(key)
+            });
+            map.retain(|key, value| {
+                *value += 1;
+                key % 3 != 0 && !(100..200).contains(key)
+            });
+            assert_eq!(Ok(()), retained.validate());
+            assert!(retained.entries().map(|(k, v)| (*k, *v)).eq(map.clone()));
+            assert_eq!(Some(map.values().map(|v| *v as i64).sum()), retained.aggregate(..));
+        }
+
+        // 回放日志得到的树与原来的树相同
+        let path = std::env::temp_dir().join(format!("b_plus_tree_remove_range_{}.bin", std::process::id()));
+        let mut tree = BPlusTree::new(String::from("tree1"), 3, 4);
+        tree.start_trace(std::io::BufWriter::new(std::fs::File::create(&path).unwrap())).unwrap();
+        for i in 0..100 {
+            tree.insert(i, i);
+        }
+        tree.remove_range(20..40);
+        tree.drain(60..);
+        tree.retain(|key, value| {
+            *value *= 2;
+            key % 2 == 0
+        });
+        tree.stop_trace().unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let report = replay_trace(bytes.as_slice()).unwrap();
+        assert!(matches!(report.outcome, ReplayOutcome::Completed { .. }));
+        assert_eq!(tree, report.tree);
+    }
+
+    #[test]
+    fn b_plus_tree_batch_test() {
+        use crate::index::b_plus_tree_aggregate::Sum;
+        use std::collections::BTreeMap;
+
+        for (internal_max_size, leaf_max_size) in [(3, 3), (3, 4), (5, 5), (4, 16)] {
+            let mut tree = BPlusTree::new(String::from("tree1"), internal_max_size, leaf_max_size);
+            tree.set_check_invariants(true);
+            tree.set_aggregator(Sum);
+            let mut map = BTreeMap::new();
+
+            let entries: Vec<(i32, i32)> = (0..300).map(|i| ((i * 53) % 311, i)).chain([(5, -1), (5, -2)]).collect();
+            let mut expected_count = 0;
+            for (key, value) in &entries {
+                if !map.contains_key(key) {
+                    map.insert(*key, *value);
+                    expected_count += 1;
+                }
+            }
+            assert_eq!(expected_count, tree.insert_batch(entries.clone()));
+            assert_eq!(0, tree.insert_batch(entries));
+            assert_eq!(Ok(()), tree.validate());
+            assert!(tree.entries().map(|(k, v)| (*k, *v)).eq(map.clone()));
+
+            let keys: Vec<i32> = (-10..330).rev().step_by(3).collect();
+            let expected: Vec<Option<i32>> = keys.iter().map(|key| map.get(key).copied()).collect();
+            assert_eq!(expected, tree.get_many(&keys));
+
+            let removed: Vec<i32> = (0..400).filter(|key| key % 4 != 1).chain([8, 8]).collect();
+            let expected_count = map.keys().filter(|key| *key % 4 != 1).count();
+            map.retain(|key, _| key % 4 == 1);
+            assert_eq!(expected_count, tree.remove_batch(removed));
+            assert_eq!(Ok(()), tree.validate());
+            assert!(tree.entries().map(|(k, v)| (*k, *v)).eq(map.clone()));
+            assert_eq!(Some(map.values().map(|v| *v as i64).sum()), tree.aggregate(..));
+
+            assert_eq!(map.len(), tree.remove_batch(map.keys().copied().collect::<Vec<i32>>()));
+            assert!(tree.is_empty());
+            assert_eq!(vec![None, None], tree.get_many(&[1, 2]));
+        }
+    }
+
+    #[test]
+    fn b_plus_tree_write_batch_test() {
+        use crate::index::b_plus_tree_write_batch::WriteBatch;
+        use std::collections::BTreeMap;
+
+        let mut tree = BPlusTree::new(String::from("tree1"), 3, 4);
+        let mut map = BTreeMap::new();
+        for i in (0..100).step_by(2) {
+            tree.insert(i, i);
+            map.insert(i, i);
+        }
+
+        let mut batch = WriteBatch::new();
+        assert!(batch.is_empty());
+        batch.put(1, 10);
+        batch.put(4, 40);
+        batch.delete(6);
+        batch.delete(7);
+        batch.put(200, 2000);
+        batch.put(8, 80);
+        batch.delete(8);
+        batch.put(-1, -10);
+        assert_eq!(7, batch.len());
+        for (key, value) in [(1, Some(10)), (4, Some(40)), (6, None), (7, None), (200, Some(2000)), (8, None), (-1, Some(-10))] {
+            match value {
+                Some(value) => map.insert(key, value),
+                None => map.remove(&key)
+            };
+        }
+
+        // 提交之前就能读到暂存的修改，树本身不变
+        assert_eq!(Some(40), batch.get(&tree, 4));
+        assert_eq!(None, batch.get(&tree, 6));
+        assert_eq!(Some(10), batch.get(&tree, 10));
+        assert_eq!(None, batch.get(&tree, 11));
+        assert!(batch.iter(&tree).eq(map.clone()));
+        assert_eq!(Some(4), tree.get_value(4));
+        assert_eq!(Some(6), tree.get_value(6));
+
+        tree.apply(batch.clone());
+        assert_eq!(Ok(()), tree.validate());
+        assert!(tree.entries().map(|(k, v)| (*k, *v)).eq(map.clone()));
+        assert!(WriteBatch::new().iter(&tree).eq(map.clone()));
+
+        // 中途出错时撤销已经应用的修改
+        let root_page = tree.get_root_page().unwrap();
+        let last_index = root_page.borrow().get_size() - 1;
+        let last_child = root_page.borrow().value_at(last_index);
+        root_page.borrow_mut().set_value_at(last_index, ValueType::Page(None));
+        let mut batch = WriteBatch::new();
+        batch.put(0, 100);
+        batch.put(3, 30);
+        batch.delete(2);
+        batch.put(98, 980);
+        assert!(matches!(tree.try_apply(batch), Err(BPlusTreeError::Corrupted(_))));
+        root_page.borrow_mut().set_value_at(last_index, last_child);
+        assert_eq!(Ok(()), tree.validate());
+        assert!(tree.entries().map(|(k, v)| (*k, *v)).eq(map));
+    }
+
+    #[test]
+    fn b_plus_tree_merge_value_test() {
+        use crate::index::b_plus_tree_aggregate::Sum;
+        use crate::index::b_plus_tree_merge::{Increment, KeepMax, MergeOperator};
+        use std::collections::BTreeMap;
+
+        let mut tree = BPlusTree::new(String::from("tree1"), 3, 4);
+        assert_eq!(Err(BPlusTreeError::NoMergeOperator), tree.try_merge_value(1, 1));
+        assert!(tree.is_empty());
+
+        let path = std::env::temp_dir().join(format!("b_plus_tree_merge_value_{}.bin", std::process::id()));
+        tree.start_trace(std::io::BufWriter::new(std::fs::File::create(&path).unwrap())).unwrap();
+        tree.set_check_invariants(true);
+        tree.set_aggregator(Sum);
+        tree.set_merge_operator(Increment);
+        let mut counters = BTreeMap::new();
+        for i in 0..500 {
+            let key = (i * 37) % 61;
+            assert_eq!(*counters.entry(key).and_modify(|count| *count += i).or_insert(i), tree.merge_value(key, i));
+        }
+        assert_eq!(Ok(()), tree.validate());
+        assert!(tree.entries().map(|(k, v)| (*k, *v)).eq(counters.clone()));
+        assert_eq!(Some(counters.values().map(|v| *v as i64).sum()), tree.aggregate(..));
+
+        tree.set_merge_operator(KeepMax);
+        assert_eq!(counters[&0].max(1_000_000), tree.merge_value(0, 1_000_000));
+        assert_eq!(counters[&1], tree.merge_value(1, -5));
+        assert_eq!(-5, tree.merge_value(-1, -5));
+
+        // 自定义的合并函数，比如把operand作为低位追加到原来的value中
+        struct AppendDigit;
+        impl MergeOperator for AppendDigit {
+            fn merge(&self, _key: i32, existing: Option<i32>, operand: i32) -> i32 {
+                existing.unwrap_or(0) * 10 + operand
+            }
+        }
+        tree.set_merge_operator(AppendDigit);
+        for digit in [1, 2, 3] {
+            tree.merge_value(1000, digit);
+        }
+        assert_eq!(Some(123), tree.get_value(1000));
+        assert_eq!(tree.clone().merge_value(1000, 4), 1234);
+
+        // 日志中记录的是合并后的结果，回放不需要合并函数
+        tree.stop_trace().unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let report = replay_trace(bytes.as_slice()).unwrap();
+        assert!(matches!(report.outcome, ReplayOutcome::Completed { .. }));
+        assert_eq!(tree, report.tree);
+
+        tree.remove_merge_operator();
+        assert!(std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| tree.merge_value(1, 1))).is_err());
+    }
+
+    #[test]
+    fn b_plus_tree_compare_and_swap_test() {
+        let mut tree = BPlusTree::new(String::from("tree1"), 3, 4);
+        for i in 0..50 {
+            tree.insert(i, i * 10);
+        }
+
+        // 期望的value不一致时不修改并返回当前的value
+        assert_eq!(Err(Some(50)), tree.compare_and_swap(5, Some(51), Some(0)));
+        assert_eq!(Err(Some(50)), tree.compare_and_swap(5, None, Some(0)));
+        assert_eq!(Err(None), tree.compare_and_swap(100, Some(1), Some(0)));
+        assert_eq!(Some(50), tree.get_value(5));
+        assert_eq!(None, tree.get_value(100));
+
+        assert_eq!(Ok(Some(50)), tree.compare_and_swap(5, Some(50), Some(55)));
+        assert_eq!(Some(55), tree.get_value(5));
+        assert_eq!(Ok(None), tree.compare_and_swap(100, None, Some(1000)));
+        assert_eq!(Some(1000), tree.get_value(100));
+        assert_eq!(Ok(Some(60)), tree.compare_and_swap(6, Some(60), None));
+        assert_eq!(None, tree.get_value(6));
+        assert_eq!(Ok(None), tree.compare_and_swap(6, None, None));
+        assert_eq!(50, tree.len());
+
+        assert_eq!(Err(70), tree.insert_if_absent(7, 0));
+        assert_eq!(Ok(()), tree.insert_if_absent(6, 66));
+        assert_eq!(Err(66), tree.insert_if_absent(6, 0));
+        assert_eq!(51, tree.len());
+        assert_eq!(Ok(()), tree.validate());
+
+        // 叶子节点中直接写入以及需要分裂、合并的情况下count和聚合值都保持正确
+        use crate::index::b_plus_tree_aggregate::Sum;
+        use std::collections::BTreeMap;
+        let mut tree = BPlusTree::new(String::from("tree1"), 3, 4);
+        tree.set_check_invariants(true);
+        tree.set_aggregator(Sum);
+        let mut map = BTreeMap::new();
+        for i in 0..60 {
+            assert_eq!(Ok(None), tree.compare_and_swap(i * 7 % 60, None, Some(i)));
+            map.insert(i * 7 % 60, i);
+        }
+        for (key, value) in map.iter_mut() {
+            assert_eq!(Ok(Some(*value)), tree.compare_and_swap(*key, Some(*value), Some(*value + 1)));
+            *value += 1;
+        }
+        for key in (0..60).step_by(2) {
+            let current = map.remove(&key);
+            assert_eq!(Err(current), tree.compare_and_swap(key, None, None));
+            assert_eq!(Ok(current), tree.compare_and_swap(key, current, None));
+        }
+        assert_eq!(map.len(), tree.len());
+        assert_eq!(map.len(), tree.count_range(..));
+        assert_eq!(Some(map.values().map(|value| *value as i64).sum::<i64>()), tree.aggregate(..));
+    }
+
+    #[test]
+    fn b_plus_tree_change_subscription_test() {
+        use crate::index::b_plus_tree_changes::{Change, ChangeEvent};
+
+        let mut tree = BPlusTree::new(String::from("tree1"), 3, 4);
+        let mut subscription = tree.subscribe(..);
+        assert_eq!(Err(BPlusTreeError::ChangeLogDisabled), tree.poll(&mut subscription));
+
+        tree.enable_change_log(100);
+        let mut all = tree.subscribe(..);
+        let mut low = tree.subscribe(0..10);
+        tree.insert(1, 10);
+        tree.insert(1, 11);
+        tree.insert(20, 200);
+        tree.extend([(1, 12)]);
+        tree.remove(1);
+        tree.remove(1);
+        tree.get_value(20);
+        assert_eq!(4, tree.get_next_sequence());
+        assert_eq!(Ok(vec![
+            ChangeEvent { sequence: 0, key: 1, change: Change::Inserted { value: 10 } },
+            ChangeEvent { sequence: 1, key: 20, change: Change::Inserted { value: 200 } },
+            ChangeEvent { sequence: 2, key: 1, change: Change::Updated { previous: 10, value: 12 } },
+            ChangeEvent { sequence: 3, key: 1, change: Change::Removed }
+        ]), tree.poll(&mut all));
+        assert_eq!(Ok(vec![]), tree.poll(&mut all));
+        assert_eq!(vec![0, 2, 3], tree.poll(&mut low).unwrap().iter().map(|event| event.sequence).collect::<Vec<u64>>());
+        assert_eq!(4, low.get_next_sequence());
+
+        // 批量操作产生每个key的事件
+        for i in 0..10 {
+            tree.insert(i, i);
+        }
+        tree.remove_range(2..5);
+        let right = tree.split_off(8);
+        assert_eq!(vec![8, 9, 20], right.entries().map(|(key, _)| *key).collect::<Vec<i32>>());
+        tree.clear();
+        let events = tree.poll(&mut low).unwrap();
+        assert_eq!(10, events.iter().filter(|event| matches!(event.change, Change::Inserted { .. })).count());
+        let mut removed: Vec<i32> = events.iter().filter(|event| event.change == Change::Removed).map(|event| event.key).collect();
+        removed.sort();
+        assert_eq!((0..10).collect::<Vec<i32>>(), removed);
+        assert!(events.windows(2).all(|pair| pair[0].sequence < pair[1].sequence));
+        assert_eq!(vec![(20, Change::Removed)], tree.poll(&mut all).unwrap().into_iter().filter(|event| event.key >= 10).map(|event| (event.key, event.change)).collect::<Vec<(i32, Change)>>());
+
+        // 从保存的序号恢复订阅，事件已经被挤出变更日志时返回错误
+        let sequence = tree.get_next_sequence();
+        for i in 0..150 {
+            tree.insert(i, i);
+        }
+        let next = tree.get_next_sequence();
+        assert_eq!(Err(BPlusTreeError::SequenceUnavailable { sequence, oldest: next - 100, next }), tree.subscribe_from(.., sequence));
+        assert!(tree.poll(&mut low).is_err());
+        let mut resumed = tree.subscribe_from(140.., next - 20).unwrap();
+        assert_eq!((140..150).collect::<Vec<i32>>(), tree.poll(&mut resumed).unwrap().iter().map(|event| event.key).collect::<Vec<i32>>());
+
+        assert_eq!(Some(next - 100), tree.get_oldest_sequence());
+        // 落后的订阅一直返回错误，不会跳过已经被挤出的事件
+        let low_sequence = low.get_next_sequence();
+        assert_eq!(Err(BPlusTreeError::SequenceUnavailable { sequence: low_sequence, oldest: next - 100, next }), tree.poll(&mut low));
+        assert_eq!(low_sequence, low.get_next_sequence());
+
+        tree.disable_change_log();
+        assert_eq!(Err(BPlusTreeError::ChangeLogDisabled), tree.poll(&mut resumed));
+        assert_eq!(None, tree.get_oldest_sequence());
+
+        // 重新打开之后序号不会重复，旧的订阅返回错误而不是读到新日志中的事件
+        tree.insert(-1, -1);
+        assert_eq!(next + 1, tree.get_next_sequence());
+        tree.enable_change_log(100);
+        tree.insert(-2, -2);
+        assert_eq!(Err(BPlusTreeError::SequenceUnavailable { sequence: next, oldest: next + 1, next: next + 2 }), tree.poll(&mut resumed));
+        assert_eq!(Ok(vec![ChangeEvent { sequence: next + 1, key: -2, change: Change::Inserted { value: -2 } }]),
+                   tree.subscribe_from(.., next + 1).and_then(|mut subscription| tree.poll(&mut subscription)));
+        let mut fresh = tree.subscribe(..);
+        tree.enable_change_log(100);
+        assert!(tree.poll(&mut fresh).is_err());
+    }
+
+    #[test]
+    fn b_plus_tree_walk_corrupted_test() {
+        let mut tree = BPlusTree::new(String::from("tree1"), 3, 4);
+        for i in 0..20 {
+            tree.insert(i, i);
+        }
+
+        // 诊断工具在孩子指针为空的树上不应该panic
+        let root_page = tree.get_root_page().unwrap();
+        let root_size = root_page.borrow().get_size();
+        for index in [0, root_size - 1] {
+            let child_page = root_page.borrow().value_at(index);
+            root_page.borrow_mut().set_value_at(index, ValueType::Page(None));
+            assert!(tree.validate().is_err());
+            assert!(tree.stats().leaf_page_count > 0);
+            assert!(!tree.to_dot().is_empty());
+            assert!(!tree.to_json().is_empty());
+            assert!(!tree.to_ascii().is_empty());
+
+            let mut visitor = RecordingVisitor::default();
+            tree.walk(WalkOrder::BreadthFirst, &mut visitor);
+            assert_eq!(root_size - 1, visitor.entered[0].child_ids.len());
+            assert_eq!(root_size - 2, visitor.entered[0].keys.len());
+            root_page.borrow_mut().set_value_at(index, child_page);
+        }
+        assert_eq!(Ok(()), tree.validate());
+    }
+
+    #[test]
+    fn b_plus_tree_trace_populated_test() {
+        let path = std::env::temp_dir().join(format!("b_plus_tree_trace_populated_{}.bin", std::process::id()));
+        let mut tree = BPlusTree::new(String::from("tree1"), 3, 4);
+        for i in (0..10).rev() {
+            tree.insert(i, i * 10);
+        }
+        tree.start_trace(std::io::BufWriter::new(std::fs::File::create(&path).unwrap())).unwrap();
+        tree.get_value(5);
+        tree.remove(3);
+        tree.insert(3, 33);
+        tree.stop_trace().unwrap();
+
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let ops: Vec<TraceOp> = TraceReader::new(bytes.as_slice()).unwrap().map(|record| record.unwrap().op).collect();
+        assert_eq!(13, ops.len());
+        assert_eq!(TraceOp::Insert { key: 0, value: 0, inserted: true }, ops[0]);
+        assert_eq!(TraceOp::Insert { key: 9, value: 90, inserted: true }, ops[9]);
+        assert_eq!(TraceOp::Get { key: 5, value: Some(50) }, ops[10]);
+
+        let report = replay_trace(bytes.as_slice()).unwrap();
+        assert_eq!(ReplayOutcome::Completed { op_count: 13 }, report.outcome);
+        assert!(report.tree.entries().eq(tree.entries()));
+    }
+
+    #[test]
+    fn b_plus_tree_internal_reads_not_traced_test() {
+        use crate::index::b_plus_tree_write_batch::WriteBatch;
+
+        let path = std::env::temp_dir().join(format!("b_plus_tree_internal_reads_{}.bin", std::process::id()));
+        let mut tree = BPlusTree::new(String::from("tree1"), 3, 4);
+        tree.start_trace(std::io::BufWriter::new(std::fs::File::create(&path).unwrap())).unwrap();
+        let mut batch = WriteBatch::new();
+        for i in 0..10 {
+            batch.put(i, i);
+        }
+        batch.delete(20);
+        assert_eq!(Some(3), batch.get(&tree, 3));
+        assert_eq!(None, batch.get(&tree, 20));
+        tree.apply(batch);
+        assert_eq!(Ok(Some(1)), tree.compare_and_swap(1, Some(1), Some(10)));
+        assert_eq!(Err(Some(10)), tree.compare_and_swap(1, Some(1), Some(11)));
+        assert_eq!(Err(2), tree.insert_if_absent(2, 20));
+        tree.stop_trace().unwrap();
+
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let ops: Vec<TraceOp> = TraceReader::new(bytes.as_slice()).unwrap().map(|record| record.unwrap().op).collect();
+        assert_eq!(11, ops.len());
+        assert!(ops.iter().all(|op| !matches!(op, TraceOp::Get { .. })));
+        assert_eq!(TraceOp::Update { key: 1, value: 10, previous: Some(1) }, ops[10]);
+        assert_eq!(tree, replay_trace(bytes.as_slice()).unwrap().tree);
+    }
+
+    #[test]
+    fn b_plus_tree_entries_mut_traced_test() {
+        use crate::index::b_plus_tree_changes::{Change, ChangeEvent};
+
+        let path = std::env::temp_dir().join(format!("b_plus_tree_entries_mut_traced_{}.bin", std::process::id()));
+        let mut tree = BPlusTree::new(String::from("tree1"), 3, 4);
+        tree.enable_change_log(100);
+        let mut subscription = tree.subscribe(..);
+        tree.start_trace(std::io::BufWriter::new(std::fs::File::create(&path).unwrap())).unwrap();
+        tree.insert(1, 10);
+        for (_, value) in &mut tree {
+            *value = 42;
+        }
+        assert_eq!(Ok(vec![
+            ChangeEvent { sequence: 0, key: 1, change: Change::Inserted { value: 10 } },
+            ChangeEvent { sequence: 1, key: 1, change: Change::Updated { previous: 10, value: 42 } }
+        ]), tree.poll(&mut subscription));
+
+        for i in 2..20 {
+            tree.insert(i, i);
+        }
+        let mut values: Vec<&mut i32> = tree.entries_mut().filter(|(key, _)| *key % 3 == 0).map(|(_, value)| value).collect();
+        for value in values.iter_mut() {
+            **value += 100;
+        }
+        // 没有修改的value不产生事件
+        for _ in tree.entries_mut() {}
+        assert_eq!(Some(103), tree.get_value(3));
+        tree.insert(3, 0);
+        tree.remove(6);
+        tree.stop_trace().unwrap();
+
+        let events = tree.poll(&mut subscription).unwrap();
+        let updated: Vec<(i32, Change)> = events.iter().filter(|event| matches!(event.change, Change::Updated { .. }))
+            .map(|event| (event.key, event.change)).collect();
+        assert_eq!((1..7).map(|i| (i * 3, Change::Updated { previous: i * 3, value: i * 3 + 100 })).collect::<Vec<(i32, Change)>>(), updated);
+
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let report = replay_trace(bytes.as_slice()).unwrap();
+        assert!(matches!(report.outcome, ReplayOutcome::Completed { .. }), "{}", report.outcome);
+        assert_eq!(tree, report.tree);
+    }
+
+    #[test]
+    fn b_plus_tree_trace_failed_operation_test() {
+        use crate::index::b_plus_tree_trace::{TraceRecord, TraceStatus};
+
+        let path = std::env::temp_dir().join(format!("b_plus_tree_trace_failed_{}.bin", std::process::id()));
+        let mut tree = BPlusTree::new(String::from("tree1"), 3, 4);
+        tree.start_trace(std::io::BufWriter::new(std::fs::File::create(&path).unwrap())).unwrap();
+        for i in 0..20 {
+            tree.insert(i, i);
+        }
+
+        // 失败的操作也要写入日志
+        let root_page = tree.get_root_page().unwrap();
+        let last_index = root_page.borrow().get_size() - 1;
+        let last_child = root_page.borrow().value_at(last_index);
+        root_page.borrow_mut().set_value_at(last_index, ValueType::Page(None));
+        assert!(matches!(tree.try_insert(100, 100), Err(BPlusTreeError::Corrupted(_))));
+        assert!(std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| tree.get_value(101))).is_err());
+        root_page.borrow_mut().set_value_at(last_index, last_child);
+        tree.insert(50, 50);
+        tree.stop_trace().unwrap();
+
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let records: Vec<TraceRecord> = TraceReader::new(bytes.as_slice()).unwrap().map(|record| record.unwrap()).collect();
+        assert_eq!(23, records.len());
+        assert_eq!(TraceRecord { op: TraceOp::Insert { key: 100, value: 100, inserted: false }, status: TraceStatus::Failed }, records[20]);
+        assert_eq!(TraceRecord { op: TraceOp::Get { key: 101, value: None }, status: TraceStatus::Failed }, records[21]);
+        assert_eq!(TraceRecord { op: TraceOp::Insert { key: 50, value: 50, inserted: true }, status: TraceStatus::Completed }, records[22]);
+
+        // 回放停在失败的操作上
+        let report = replay_trace(bytes.as_slice()).unwrap();
+        assert_eq!(ReplayOutcome::Unreproduced {
+            index: 20,
+            op: TraceOp::Insert { key: 100, value: 100, inserted: false },
+            status: TraceStatus::Failed
+        }, report.outcome);
+        assert_eq!(Some(100), report.tree.get_value(100));
+
+        // 日志在操作的参数之后结束，说明进程在执行这个操作时退出了
+        let truncated = &bytes[..bytes.len() - 2];
+        let records: Vec<TraceRecord> = TraceReader::new(truncated).unwrap().map(|record| record.unwrap()).collect();
+        assert_eq!(TraceRecord { op: TraceOp::Insert { key: 50, value: 50, inserted: false }, status: TraceStatus::Interrupted }, records[22]);
+    }
+
+    #[test]
+    fn b_plus_tree_page_move_corrupted_test() {
+        let mut tree = BPlusTree::new(String::from("tree1"), 3, 4);
+        for i in 0..20 {
+            tree.insert(i, i);
+        }
+
+        // 孩子指针为空时移动元素返回错误，并且不修改节点
+        let root_page = tree.get_root_page().unwrap();
+        let root_size = root_page.borrow().get_size();
+        let child_page = root_page.borrow().value_at(root_size - 1);
+        root_page.borrow_mut().set_value_at(root_size - 1, ValueType::Page(None));
+        let recipient = BPlusTreePage::new(BPlusTreePageType::InternalPage, 3, None);
+        let page_id = root_page.borrow().get_page_id();
+        assert_eq!(Err(TreeCorruption::InvalidPage { page_id }), root_page.borrow_mut().move_all_to(recipient.clone(), 0));
+        assert_eq!(Err(TreeCorruption::InvalidPage { page_id }), root_page.borrow_mut().move_half_to(recipient.clone()));
+        assert_eq!(Err(TreeCorruption::InvalidPage { page_id }), root_page.borrow_mut().move_last_to_front_of(recipient.clone(), 0));
+        assert_eq!(root_size, root_page.borrow().get_size());
+        assert_eq!(0, recipient.borrow().get_size());
+        root_page.borrow_mut().set_value_at(root_size - 1, child_page);
+        assert_eq!(Ok(()), tree.validate());
+
+        let empty_page = BPlusTreePage::new(BPlusTreePageType::LeafPage, 4, None);
+        let empty_page_id = empty_page.borrow().get_page_id();
+        assert_eq!(Err(TreeCorruption::Underflow { page_id: empty_page_id, size: 0, min_size: 1 }),
+                   empty_page.borrow_mut().move_first_to_end_of(recipient.clone(), 0));
+    }
+
+    #[test]
+    fn b_plus_tree_into_iter_test() {
+        let tree: BPlusTree = (0..100).rev().map(|i| (i, i * 2)).collect();
+        let mut iter = tree.into_iter();
+        assert_eq!(100, iter.len());
+        assert_eq!(Some((0, 0)), iter.next());
+        assert_eq!(99, iter.len());
+        assert_eq!((1..100).map(|i| (i, i * 2)).collect::<Vec<(i32, i32)>>(), iter.collect::<Vec<(i32, i32)>>());
+        assert_eq!(0, BPlusTree::default().into_iter().count());
+    }
+
+    #[test]
+    fn b_plus_tree_summary_path_test() {
+        use crate::index::b_plus_tree_aggregate::Sum;
+        use crate::index::b_plus_tree_merge::Increment;
+        use std::collections::BTreeMap;
+
+        // 每次修改之后由check_invariants()检查沿路径更新的count和聚合值
+        for (internal_max_size, leaf_max_size) in [(3, 3), (3, 4), (4, 3), (5, 6)] {
+            let mut tree = BPlusTree::new(String::from("tree1"), internal_max_size, leaf_max_size);
+            tree.set_check_invariants(true);
+            tree.set_aggregator(Sum);
+            tree.set_merge_operator(Increment);
+            let mut map = BTreeMap::new();
+            let mut x: u32 = 12345;
+            for _ in 0..2000 {
+                x = x.wrapping_mul(1103515245).wrapping_add(12345);
+                let key = ((x >> 16) % 200) as i32;
+                match (x >> 8) % 5 {
+                    0 | 1 => {
+                        assert_eq!(!map.contains_key(&key), tree.insert(key, key * 3));
+                        map.entry(key).or_insert(key * 3);
+                    }
+                    2 => {
+                        assert_eq!(map.remove(&key).is_some(), tree.try_remove(key).unwrap());
+                    }
+                    3 => {
+                        let value = map.get(&key).copied().unwrap_or(0) + 5;
+                        assert_eq!(value, tree.merge_value(key, 5));
+                        map.insert(key, value);
+                    }
+                    _ => {
+                        let keys: Vec<i32> = (key..key + 8).collect();
+                        tree.remove_batch(keys.iter().copied().filter(|key| key % 2 == 0));
+                        tree.insert_batch(keys.iter().map(|key| (*key, -key)));
+                        for key in keys {
+                            if key % 2 == 0 {
+                                map.insert(key, -key);
+                            } else {
+                                map.entry(key).or_insert(-key);
+                            }
+                        }
+                    }
+                }
+            }
+            assert_eq!(map.len(), tree.len());
+            assert_eq!(Some(map.values().map(|value| *value as i64).sum::<i64>()), tree.aggregate(..));
+            assert_eq!(map.range(50..150).count(), tree.count_range(50..150));
+        }
+    }
+
+    #[test]
+    fn b_plus_tree_append_stats_test() {
+        for (left_len, right_len) in [(200, 3), (3, 200), (100, 100)] {
+            let mut left = BPlusTree::new(String::from("tree1"), 3, 4);
+            let mut right = BPlusTree::new(String::from("tree2"), 3, 4);
+            for i in 0..left_len {
+                left.insert(i, i);
+            }
+            for i in 0..right_len {
+                right.insert(1000 + i, i);
+            }
+
+            // 拼接和拆分整棵树不计入split/merge/redistribute的次数，也不录制帧
+            let stats = left.stats();
+            left.start_recording(FrameFormat::Json);
+            left.append(&mut right);
+            assert_eq!(Ok(()), left.validate());
+            let mut right = left.split_off(1000);
+            assert_eq!(Ok(()), left.validate());
+            assert_eq!(Ok(()), right.validate());
+            assert!(left.stop_recording().is_empty());
+            assert_eq!((stats.split_count, stats.merge_count, stats.redistribute_count),
+                       (left.stats().split_count, left.stats().merge_count, left.stats().redistribute_count));
+            assert_eq!((0, 0), (right.stats().merge_count, right.stats().redistribute_count));
+
+            // 之后的插入仍然正常计数
+            right.start_recording(FrameFormat::Json);
+            let split_count = right.stats().split_count;
+            for i in 0..10 {
+                right.insert(2000 + i, i);
+            }
+            assert!(right.stats().split_count > split_count);
+            assert!(!right.stop_recording().is_empty());
+        }
+    }
+}
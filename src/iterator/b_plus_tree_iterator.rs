@@ -1,15 +1,18 @@
-use crate::page::b_plus_tree_page::{Page, ValueType};
+use std::{slice, vec};
+use crate::page::b_plus_tree_page::{BPlusTreePage, MappingType, Page, RcPage, ValueType};
 
 pub struct BPlusTreeIter {
     cur_page_: Page,
-    index_: usize
+    index_: usize,
+    end_key_: Option<i32> // 遍历到第一个>=end_key_的key时停止，None表示遍历到最后一个叶子节点
 }
 
 impl BPlusTreeIter {
-    pub fn new(cur_page: Page, index: usize) -> Self {
+    pub fn new(cur_page: Page, index: usize, end_key: Option<i32>) -> Self {
         BPlusTreeIter {
             cur_page_: cur_page,
-            index_: index
+            index_: index,
+            end_key_: end_key
         }
     }
 }
@@ -40,6 +43,13 @@ impl Iterator for BPlusTreeIter {
             self.index_ = 0;
         }
 
+        if let Some(end_key) = self.end_key_ {
+            if self.cur_page_.as_ref().unwrap().borrow().key_at(self.index_) >= end_key {
+                self.cur_page_ = None;
+                return None;
+            }
+        }
+
         return if let ValueType::Value(value) = self.cur_page_.as_ref().unwrap().borrow().value_at(self.index_) {
             self.index_ += 1;
             value
@@ -47,4 +57,133 @@ impl Iterator for BPlusTreeIter {
             None
         }
     }
-}
\ No newline at end of file
+}
+/// BPlusTree::entries()的返回值，按key从小到大返回(&key, &value)
+pub struct BPlusTreeEntries<'a> {
+    entries_: slice::Iter<'a, MappingType>,
+    next_page_: Page // 当前叶子节点遍历完之后要访问的叶子节点
+}
+
+impl<'a> BPlusTreeEntries<'a> {
+    /// # Safety
+    ///
+    /// 在'a期间，从leaf_page开始的叶子节点都不能被修改或者释放
+    pub(crate) unsafe fn new(leaf_page: Page) -> Self {
+        BPlusTreeEntries {
+            entries_: [].iter(),
+            next_page_: leaf_page
+        }
+    }
+}
+
+impl<'a> Iterator for BPlusTreeEntries<'a> {
+    type Item = (&'a i32, &'a i32);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            for item in self.entries_.by_ref() {
+                if let ValueType::Value(Some(value)) = &item.value {
+                    return Some((&item.key, value));
+                }
+            }
+
+            let leaf_page = self.next_page_.take()?;
+            // SAFETY: 由new()的调用者保证节点在'a期间不被修改或者释放
+            let page: &'a BPlusTreePage = unsafe { &*leaf_page.as_ptr() };
+            self.next_page_ = page.get_next_page();
+            self.entries_ = page.get_page_data().iter();
+        }
+    }
+}
+
+/// BPlusTree::entries_mut()的返回值，按key从小到大返回(&key, &mut value)
+pub struct BPlusTreeEntriesMut<'a> {
+    entries_: slice::IterMut<'a, MappingType>,
+    next_page_: Page,
+    visited_pages_: Option<&'a mut Vec<RcPage>> // 不为None时记录访问过的叶子节点
+}
+
+impl<'a> BPlusTreeEntriesMut<'a> {
+    /// # Safety
+    ///
+    /// 在'a期间，从leaf_page开始的叶子节点只能通过返回的迭代器访问
+    pub(crate) unsafe fn new(leaf_page: Page, visited_pages: Option<&'a mut Vec<RcPage>>) -> Self {
+        BPlusTreeEntriesMut {
+            entries_: [].iter_mut(),
+            next_page_: leaf_page,
+            visited_pages_: visited_pages
+        }
+    }
+}
+
+impl<'a> Iterator for BPlusTreeEntriesMut<'a> {
+    type Item = (&'a i32, &'a mut i32);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            for MappingType { key, value, .. } in self.entries_.by_ref() {
+                if let ValueType::Value(Some(value)) = value {
+                    let key: &'a i32 = key;
+                    return Some((key, value));
+                }
+            }
+
+            let leaf_page = self.next_page_.take()?;
+            if let Some(visited_pages) = self.visited_pages_.as_mut() {
+                visited_pages.push(leaf_page.clone());
+            }
+            // SAFETY: 由new()的调用者保证节点在'a期间只被这个迭代器访问，
+            // 每个叶子节点只会被访问一次，所以返回的可变引用互不重叠
+            let page: &'a mut BPlusTreePage = unsafe { &mut *leaf_page.as_ptr() };
+            // 先取出next_page_，之后page的可变借用全部交给entries_
+            self.next_page_ = page.get_next_page();
+            self.entries_ = page.get_page_data_mut().iter_mut();
+        }
+    }
+}
+
+/// BPlusTree::into_iter()的返回值，按key从小到大返回(key, value)
+///
+/// 每次只取出一个叶子节点中的元素，不会把整棵树复制到新的Vec中
+pub struct BPlusTreeIntoIter {
+    entries_: vec::IntoIter<MappingType>,
+    next_page_: Page,
+    len_: usize // 还没有返回的键值对个数
+}
+
+impl BPlusTreeIntoIter {
+    /// leaf_page是最左边的叶子节点，调用者保证之后不再通过树访问这些叶子节点
+    pub(crate) fn new(leaf_page: Page, len: usize) -> Self {
+        BPlusTreeIntoIter {
+            entries_: Vec::new().into_iter(),
+            next_page_: leaf_page,
+            len_: len
+        }
+    }
+}
+
+impl Iterator for BPlusTreeIntoIter {
+    type Item = (i32, i32);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            for item in self.entries_.by_ref() {
+                if let ValueType::Value(Some(value)) = item.value {
+                    self.len_ -= 1;
+                    return Some((item.key, value));
+                }
+            }
+
+            let leaf_page = self.next_page_.take()?;
+            let mut page = leaf_page.borrow_mut();
+            self.next_page_ = page.get_next_page();
+            self.entries_ = page.take_page_data().into_iter();
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len_, Some(self.len_))
+    }
+}
+
+impl ExactSizeIterator for BPlusTreeIntoIter {}
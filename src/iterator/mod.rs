@@ -14,18 +14,31 @@ mod tests {
         }
 
         tree.draw();
+        assert_eq!((0..=10).collect::<Vec<i32>>(), tree.iter().collect::<Vec<i32>>());
 
-        // tree.remove(0);
-        // tree.remove(1);
-        // tree.remove(2);
-        // tree.remove(3);
-        // tree.remove(4);
-        // tree.remove(7);
+        tree.remove(0);
+        tree.remove(1);
+        tree.remove(2);
+        tree.remove(3);
+        tree.remove(4);
+        tree.remove(7);
 
-        // tree.print();
+        tree.print();
+        assert_eq!(vec![5, 6, 8, 9, 10], tree.iter().collect::<Vec<i32>>());
+    }
+
+    #[test]
+    fn b_plus_tree_range_test() {
+        let mut tree = BPlusTree::new(String::from("tree1"), 3, 3);
+
+        for i in 0..=10 {
+            tree.insert(i * 2, i);
+        }
 
-        // for item in tree.iter() {
-        //     print!("{} ", item);
-        // }
+        assert_eq!(vec![2, 3, 4], tree.range(3, 9).collect::<Vec<i32>>());
+        assert_eq!(vec![0, 1], tree.range(-5, 4).collect::<Vec<i32>>());
+        assert_eq!(vec![10], tree.range(20, 100).collect::<Vec<i32>>());
+        assert!(tree.range(21, 100).next().is_none());
+        assert!(tree.range(5, 5).next().is_none());
     }
 }
\ No newline at end of file
@@ -0,0 +1,4 @@
+pub mod page;
+pub mod index;
+pub mod iterator;
+pub mod shell;
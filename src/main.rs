@@ -1,7 +1,62 @@
-mod page;
-mod index;
-mod iterator;
+use std::env;
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, IsTerminal};
+use std::process;
+use b_plus_tree::shell::b_plus_tree_shell::{parse_size, BPlusTreeShell};
+
+const USAGE: &str = "usage: BPlusTree [-i internal_max_size] [-l leaf_max_size] [script]
+
+Without a script, commands are read from standard input. Type help for a list of commands.";
 
 fn main() {
+    let mut internal_max_size = 3;
+    let mut leaf_max_size = 3;
+    let mut script = None;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let result = match arg.as_str() {
+            "-i" => args.next().ok_or(String::from("-i needs a value")).and_then(|size| parse_size(&size)).map(|size| internal_max_size = size),
+            "-l" => args.next().ok_or(String::from("-l needs a value")).and_then(|size| parse_size(&size)).map(|size| leaf_max_size = size),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ if script.is_none() && !arg.starts_with('-') => {
+                script = Some(arg);
+                Ok(())
+            }
+            _ => Err(format!("unexpected argument {}", arg))
+        };
+
+        if let Err(message) = result {
+            eprintln!("error: {}\n{}", message, USAGE);
+            process::exit(2);
+        }
+    }
+
+    let mut shell = BPlusTreeShell::new(internal_max_size, leaf_max_size);
+    let mut stdout = io::stdout();
+    let result = match script {
+        Some(path) => {
+            match File::open(&path) {
+                Ok(file) => shell.run(BufReader::new(file), &mut stdout, false),
+                Err(e) => {
+                    eprintln!("error: cannot open {}: {}", path, e);
+                    process::exit(1);
+                }
+            }
+        }
+        None => {
+            let stdin = io::stdin();
+            let interactive = stdin.is_terminal();
+            shell.run(stdin.lock(), &mut stdout, interactive)
+        }
+    };
 
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
 }
@@ -1,7 +1,11 @@
 use std::cell::RefCell;
+use std::mem;
+use std::ops::Range;
 use std::rc::Rc;
+use std::slice;
 
 use std::sync::atomic::{AtomicUsize, Ordering};
+use crate::index::b_plus_tree::TreeCorruption;
 
 pub type Page = Option<Rc<RefCell<BPlusTreePage>>>;
 pub type RcPage = Rc<RefCell<BPlusTreePage>>;
@@ -10,7 +14,7 @@ pub type SizeT = usize;
 static PAGE_ID_ATOMIC: AtomicUsize  = AtomicUsize::new(0);
 
 
-#[derive(PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum BPlusTreePageType {
     InvalidIndexPage,
     InternalPage,
@@ -98,6 +102,10 @@ impl PartialEq for ValueType {
 pub struct MappingType {
     pub key: i32,
     pub value: ValueType,
+    /// 内部节点中为子树中key的个数，叶子节点中为1，所以节点内count之和就是以该节点为根的子树中key的个数
+    pub count: SizeT,
+    /// 内部节点中为子树中所有value的聚合值，由BPlusTree按设置的聚合函数维护，叶子节点中不使用
+    pub aggregate: i64
 }
 
 impl BPlusTreePage {
@@ -117,6 +125,10 @@ impl BPlusTreePage {
         self.page_id_
     }
 
+    pub fn get_page_type(&self) -> BPlusTreePageType {
+        self.page_type_
+    }
+
     pub fn is_root_page(&self) -> bool {
         self.parent_page_.is_none()
     }
@@ -190,11 +202,52 @@ impl BPlusTreePage {
         self.page_data_[index].value = value
     }
 
+    /// 按引用遍历叶子节点的键值对时使用
+    pub(crate) fn get_page_data(&self) -> &[MappingType] {
+        &self.page_data_
+    }
+
+    pub(crate) fn get_page_data_mut(&mut self) -> &mut [MappingType] {
+        &mut self.page_data_
+    }
+
+    /// 取出节点中的所有元素，只在消耗整棵树的遍历中使用
+    pub(crate) fn take_page_data(&mut self) -> Vec<MappingType> {
+        mem::take(&mut self.page_data_)
+    }
+
+    pub fn get_count_at(&self, index: usize) -> SizeT {
+        self.page_data_[index].count
+    }
+
+    pub fn set_count_at(&mut self, index: usize, count: SizeT) {
+        self.page_data_[index].count = count
+    }
+
+    pub fn get_aggregate_at(&self, index: usize) -> i64 {
+        self.page_data_[index].aggregate
+    }
+
+    pub fn set_aggregate_at(&mut self, index: usize, aggregate: i64) {
+        self.page_data_[index].aggregate = aggregate
+    }
+
+    /// 以该节点为根的子树中key的个数
+    pub fn get_subtree_count(&self) -> SizeT {
+        self.page_data_.iter().map(|item| item.count).sum()
+    }
+
     pub fn key_index(&self, key: i32) -> usize {
+        self.key_index_traced(key, |_, _| {})
+    }
+
+    /// 与key_index相同，每次比较关键字时调用on_compare(下标, 关键字)
+    pub fn key_index_traced(&self, key: i32, mut on_compare: impl FnMut(usize, i32)) -> usize {
         let mut left = 0;
         let mut right = self.get_size() as i32 - 1;
         while left <= right {
             let mid = left + (right - left) / 2;
+            on_compare(mid as usize, self.key_at(mid as usize));
             if self.key_at(mid as usize) as i32 >= key {
                 right = mid - 1;
             } else {
@@ -231,12 +284,25 @@ impl BPlusTreePage {
     }
 
     pub fn lookup(&self, key: i32) -> ValueType {
+        self.lookup_traced(key, |_, _| {}).1
+    }
+
+    /// 与lookup相同，每次比较关键字时调用on_compare(下标, 关键字)
+    ///
+    /// 同时返回命中的下标：内部节点为选中的孩子下标，叶子节点为key所在的下标，没有找到时为None
+    pub fn lookup_traced(&self, key: i32, mut on_compare: impl FnMut(usize, i32)) -> (Option<usize>, ValueType) {
         if self.is_internal_page() {
+            // 没有孩子的内部节点说明树已经损坏，交给调用者处理空指针
+            if self.get_size() == 0 {
+                return (None, ValueType::Page(None));
+            }
+
             let mut left = 1;
             let mut right = self.get_size() - 1;
 
             while left <= right {
                 let mid = left + (right - left) / 2;
+                on_compare(mid, self.key_at(mid));
                 if self.key_at(mid) > key {
                     right = mid - 1;
                 } else {
@@ -244,29 +310,35 @@ impl BPlusTreePage {
                 }
             }
 
+            // left从1开始，所以target_index - 1不会越界
             let target_index = left;
-            assert!(target_index - 1 >= 0);
-            self.value_at(target_index - 1)
+            (Some(target_index - 1), self.value_at(target_index - 1))
         } else if self.is_leaf_page() {
-            let target_index = self.key_index(key);
+            let target_index = self.key_index_traced(key, on_compare);
             if target_index == self.get_size() || self.key_at(target_index) != key {
-                ValueType::Value(None)
+                (None, ValueType::Value(None))
             } else {
-                self.page_data_[target_index].value.clone()
+                (Some(target_index), self.page_data_[target_index].value.clone())
             }
         } else {
-            ValueType::Value(None)
+            (None, ValueType::Value(None))
         }
     }
 
     pub fn create_new_root(&mut self, old_page: RcPage, new_key: i32, new_page: RcPage) {
+        let old_count = old_page.borrow().get_subtree_count();
+        let new_count = new_page.borrow().get_subtree_count();
         let item1 = MappingType {
             key: Default::default(),
-            value: ValueType::Page(Some(old_page))
+            value: ValueType::Page(Some(old_page)),
+            count: old_count,
+            aggregate: 0
         };
         let item2 = MappingType {
             key: new_key,
-            value: ValueType::Page(Some(new_page))
+            value: ValueType::Page(Some(new_page)),
+            count: new_count,
+            aggregate: 0
         };
         self.page_data_.push(item1);
         self.page_data_.push(item2);
@@ -274,9 +346,12 @@ impl BPlusTreePage {
     }
 
     // only can be invoked by internal page
+    // old_value刚刚分裂出new_value，同时更新两者的count
     pub fn insert_node_after(&mut self, old_value: RcPage, new_key: i32, new_value: RcPage) -> SizeT {
-        if let Some(old_value_index) = self.value_index(ValueType::Page(Some(old_value))) {
-            self.page_data_.insert(old_value_index + 1, MappingType { key: new_key, value: ValueType::Page(Some(new_value)) });
+        if let Some(old_value_index) = self.value_index(ValueType::Page(Some(old_value.clone()))) {
+            self.set_count_at(old_value_index, old_value.borrow().get_subtree_count());
+            let count = new_value.borrow().get_subtree_count();
+            self.page_data_.insert(old_value_index + 1, MappingType { key: new_key, value: ValueType::Page(Some(new_value)), count, aggregate: 0 });
             self.get_size()
         } else {
             // TODO
@@ -284,6 +359,13 @@ impl BPlusTreePage {
         }
     }
 
+    /// 在下标为index的位置插入子节点，不修改子节点的parent_page_
+    pub fn insert_node_at(&mut self, index: usize, key: i32, child_page: RcPage) -> SizeT {
+        let count = child_page.borrow().get_subtree_count();
+        self.page_data_.insert(index, MappingType { key, value: ValueType::Page(Some(child_page)), count, aggregate: 0 });
+        self.get_size()
+    }
+
     pub fn insert(&mut self, key: i32, value: i32) -> SizeT {
         let insert_index = self.key_index(key); // 查找第一个>=key的下标
 
@@ -295,7 +377,9 @@ impl BPlusTreePage {
         // [insert_index, size - 1] --> [insert_index + 1, size]
         self.page_data_.insert(insert_index, MappingType {
             key,
-            value: ValueType::Value(Some(value))
+            value: ValueType::Value(Some(value)),
+            count: 1,
+            aggregate: 0
         });
         self.get_size()
     }
@@ -322,82 +406,91 @@ impl BPlusTreePage {
         self.get_size()
     }
 
-    pub fn move_half_to(&mut self, recipient: RcPage) {
-        let start_index = self.get_min_size();
+    pub fn move_half_to(&mut self, recipient: RcPage) -> Result<(), TreeCorruption> {
+        // 按照非根节点的下限拆分，避免根节点拆分后左半部分只剩下get_min_size()个元素
+        let start_index = self.get_size() / 2;
         let pre_size = self.get_size();
         let move_num = pre_size - start_index;
-        let mut moved_items = self.page_data_.split_off(start_index);
-        for item in &mut moved_items {
-            match &mut item.value {
-                ValueType::Page(child_page) => {
-                    (*child_page).as_ref().unwrap().borrow_mut().parent_page_ = Some(recipient.clone());
-                }
-                ValueType::Value(_) => {
-                    // todo nothing!
-                }
-            }
+        self.move_tail_to(start_index, recipient)?;
+        assert_eq!(pre_size - move_num, self.get_size());
+        Ok(())
+    }
+
+    /// 把下标从start_index开始的所有元素移动到recipient的末尾
+    pub fn move_tail_to(&mut self, start_index: usize, recipient: RcPage) -> Result<(), TreeCorruption> {
+        if start_index > self.get_size() {
+            return Err(TreeCorruption::InvalidPage { page_id: self.page_id_ });
         }
+        self.check_child_pages(start_index..self.get_size())?;
+        let mut moved_items = self.page_data_.split_off(start_index);
+        Self::adopt_child_pages(&moved_items, &recipient);
         recipient.borrow_mut().page_data_.append(&mut moved_items);
-        assert_eq!(pre_size - move_num, self.get_size());
+        Ok(())
     }
 
-    pub fn move_all_to(&mut self, recipient: RcPage, middle_key: i32) {
-        if self.is_internal_page() {
+    pub fn move_all_to(&mut self, recipient: RcPage, middle_key: i32) -> Result<(), TreeCorruption> {
+        self.check_child_pages(0..self.get_size())?;
+        if self.is_internal_page() && self.get_size() > 0 {
             self.set_key_at(0, middle_key);
         }
 
-        let mut moved_items = self.page_data_.to_vec();
-        self.page_data_.clear();
-        for item in &mut moved_items {
-            match &mut item.value {
-                ValueType::Page(child_page) => {
-                    (*child_page).as_ref().unwrap().borrow_mut().parent_page_ = Some(recipient.clone());
-                }
-                ValueType::Value(_) => {
-                    // todo nothing!
-                }
-            }
-        }
+        let mut moved_items = mem::take(&mut self.page_data_);
+        Self::adopt_child_pages(&moved_items, &recipient);
         recipient.borrow_mut().page_data_.append(&mut moved_items);
+        Ok(())
     }
 
-    pub fn move_first_to_end_of(&mut self, recipient: RcPage, middle_key: i32) {
-        if self.is_internal_page() {
-            self.set_key_at(0, middle_key);
+    pub fn move_first_to_end_of(&mut self, recipient: RcPage, middle_key: i32) -> Result<(), TreeCorruption> {
+        if self.page_data_.is_empty() {
+            return Err(TreeCorruption::Underflow { page_id: self.page_id_, size: 0, min_size: self.get_min_size() });
         }
-        assert!(self.page_data_.len() > 0);
-        let mut first_item = self.page_data_.remove(0);
+        self.check_child_pages(0..1)?;
         if self.is_internal_page() {
-            match &mut first_item.value {
-                ValueType::Page(child_page) => {
-                    (*child_page).as_ref().unwrap().borrow_mut().parent_page_ = Some(recipient.clone());
-                }
-                ValueType::Value(_) => {
-                    // todo nothing!
-                }
-            }
+            self.set_key_at(0, middle_key);
         }
+        let first_item = self.page_data_.remove(0);
+        Self::adopt_child_pages(slice::from_ref(&first_item), &recipient);
         recipient.borrow_mut().page_data_.push(first_item);
+        Ok(())
     }
 
-    pub fn move_last_to_front_of(&mut self,  recipient: RcPage, middle_key: i32) {
-        recipient.borrow_mut().set_key_at(0, middle_key);
-        assert!(self.page_data_.len() > 0);
-        let mut last_item = self.page_data_.pop().unwrap();
+    pub fn move_last_to_front_of(&mut self,  recipient: RcPage, middle_key: i32) -> Result<(), TreeCorruption> {
+        let last_index = match self.get_size().checked_sub(1) {
+            None => {
+                return Err(TreeCorruption::Underflow { page_id: self.page_id_, size: 0, min_size: self.get_min_size() });
+            }
+            Some(last_index) => {
+                last_index
+            }
+        };
+        self.check_child_pages(last_index..last_index + 1)?;
         if self.is_internal_page() {
-            match &mut last_item.value {
-                ValueType::Page(child_page) => {
-                    (*child_page).as_ref().unwrap().borrow_mut().parent_page_ = Some(recipient.clone());
-                }
-                ValueType::Value(_) => {
-                    // todo nothing!
-                }
+            let mut recipient_page = recipient.borrow_mut();
+            if recipient_page.page_data_.is_empty() {
+                return Err(TreeCorruption::Underflow { page_id: recipient_page.page_id_, size: 0, min_size: recipient_page.get_min_size() });
             }
+            recipient_page.set_key_at(0, middle_key);
         }
+        let last_item = self.page_data_.remove(last_index);
+        Self::adopt_child_pages(slice::from_ref(&last_item), &recipient);
         recipient.borrow_mut().page_data_.insert(0, last_item);
+        Ok(())
     }
-}
-
-
 
+    /// 移动之前检查下标在range中的子节点指针，避免移动到一半才发现指针为空
+    fn check_child_pages(&self, range: Range<usize>) -> Result<(), TreeCorruption> {
+        if self.page_data_[range].iter().any(|item| matches!(item.value, ValueType::Page(None))) {
+            return Err(TreeCorruption::InvalidPage { page_id: self.page_id_ });
+        }
+        Ok(())
+    }
 
+    /// 把移动到recipient中的子节点的parent_page_指向recipient
+    fn adopt_child_pages(items: &[MappingType], recipient: &RcPage) {
+        for item in items {
+            if let ValueType::Page(Some(child_page)) = &item.value {
+                child_page.borrow_mut().parent_page_ = Some(recipient.clone());
+            }
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use crate::index::b_plus_tree::BPlusTree;
+use crate::index::b_plus_tree_trace::replay_trace;
+use crate::page::b_plus_tree_page::SizeT;
+
+const HELP: &str = "\
+commands:
+  new <internal_max_size> <leaf_max_size>   create a new empty tree
+  insert <key> <value>                      insert a key, prints false if the key already exists
+  get <key>                                 print the value of key or None
+  remove <key>                              remove a key
+  range <start> <end>                       print the values with keys in [start, end)
+  explain <key>                             print the pages visited when looking up key
+  print                                     print every page
+  draw [file.dot]                           write the Graphviz DOT output to a file or to the screen
+  stats                                     print height, page counts, fill factors and split/merge counts
+  validate                                  check the structural invariants of the tree
+  trace <file> | trace off                  start/stop logging insert, get and remove to a trace file
+  replay <file>                             replay a trace file on a new tree, stopping at the first divergence
+  help                                      print this message
+  quit | exit                               leave the shell
+lines starting with # are comments";
+
+/// 逐行解释执行命令，所有输出都写入调用者给出的out，方便交互使用和脚本回放
+pub struct BPlusTreeShell {
+    tree: BPlusTree
+}
+
+impl BPlusTreeShell {
+    pub fn new(internal_max_size: SizeT, leaf_max_size: SizeT) -> Self {
+        BPlusTreeShell {
+            tree: BPlusTree::new(String::from("shell"), internal_max_size, leaf_max_size)
+        }
+    }
+
+    pub fn get_tree(&self) -> &BPlusTree {
+        &self.tree
+    }
+
+    /// 依次执行input中的每一行，直到输入结束或者遇到quit/exit
+    ///
+    /// interactive为true时在每行前输出提示符，否则在出错的命令前输出行号，方便定位脚本中的错误
+    pub fn run(&mut self, input: impl BufRead, out: &mut impl Write, interactive: bool) -> io::Result<()> {
+        if interactive {
+            write!(out, "> ")?;
+            out.flush()?;
+        }
+
+        for (line_number, line) in input.lines().enumerate() {
+            let line = line?;
+            match self.execute(&line, out) {
+                Ok(true) => {}
+                Ok(false) => {
+                    return Ok(());
+                }
+                Err(message) => {
+                    if interactive {
+                        writeln!(out, "error: {}", message)?;
+                    } else {
+                        writeln!(out, "line {}: error: {}", line_number + 1, message)?;
+                    }
+                }
+            }
+
+            if interactive {
+                write!(out, "> ")?;
+                out.flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// 执行一条命令，返回Ok(false)表示需要退出，命令有误时返回错误信息
+    pub fn execute(&mut self, line: &str, out: &mut impl Write) -> Result<bool, String> {
+        let args: Vec<&str> = line.split_whitespace().collect();
+        if args.is_empty() || args[0].starts_with('#') {
+            return Ok(true);
+        }
+
+        let result = match (args[0], args.len()) {
+            ("new", 3) => {
+                let internal_max_size = parse_size(args[1])?;
+                let leaf_max_size = parse_size(args[2])?;
+                self.tree = BPlusTree::try_new(String::from("shell"), internal_max_size, leaf_max_size).map_err(|e| e.to_string())?;
+                Ok(())
+            }
+            ("insert", 3) => {
+                let key = parse_int(args[1])?;
+                let value = parse_int(args[2])?;
+                writeln!(out, "{}", self.tree.try_insert(key, value).map_err(|e| e.to_string())?)
+            }
+            ("get", 2) => {
+                match self.tree.try_get_value(parse_int(args[1])?).map_err(|e| e.to_string())? {
+                    Some(value) => writeln!(out, "{}", value),
+                    None => writeln!(out, "None")
+                }
+            }
+            ("remove", 2) => {
+                self.tree.try_remove(parse_int(args[1])?).map_err(|e| e.to_string())?;
+                Ok(())
+            }
+            ("range", 3) => {
+                let values: Vec<String> = self.tree.range(parse_int(args[1])?, parse_int(args[2])?).map(|value| value.to_string()).collect();
+                writeln!(out, "{}", values.join(" "))
+            }
+            ("explain", 2) => {
+                write!(out, "{}", self.tree.explain_get(parse_int(args[1])?))
+            }
+            ("print", 1) => {
+                if self.tree.is_empty() {
+                    writeln!(out, "Tree is Empty!")
+                } else {
+                    write!(out, "{}", self.tree.to_text())
+                }
+            }
+            ("draw", 1) => {
+                self.tree.render_dot(out)
+            }
+            ("draw", 2) => {
+                File::create(args[1]).and_then(|mut file| self.tree.render_dot(&mut file))
+                    .map_err(|e| format!("cannot write {}: {}", args[1], e))?;
+                Ok(())
+            }
+            ("stats", 1) => {
+                write!(out, "{}", self.tree.stats())
+            }
+            ("validate", 1) => {
+                match self.tree.validate() {
+                    Ok(()) => writeln!(out, "ok"),
+                    Err(corruption) => {
+                        return Err(format!("tree is corrupted: {}", corruption));
+                    }
+                }
+            }
+            ("trace", 2) if args[1] == "off" => {
+                self.tree.stop_trace()
+            }
+            ("trace", 2) => {
+                File::create(args[1]).and_then(|file| self.tree.start_trace(BufWriter::new(file)))
+                    .map_err(|e| format!("cannot write {}: {}", args[1], e))?;
+                Ok(())
+            }
+            ("replay", 2) => {
+                let report = File::open(args[1]).and_then(|file| replay_trace(BufReader::new(file)))
+                    .map_err(|e| format!("cannot replay {}: {}", args[1], e))?;
+                self.tree = report.tree;
+                writeln!(out, "{}", report.outcome)
+            }
+            ("help", 1) => {
+                writeln!(out, "{}", HELP)
+            }
+            ("quit", 1) | ("exit", 1) => {
+                return Ok(false);
+            }
+            _ => {
+                return Err(format!("unknown command or wrong number of arguments: {} (type help for a list of commands)", line.trim()));
+            }
+        };
+
+        result.map_err(|e| e.to_string())?;
+        Ok(true)
+    }
+}
+
+fn parse_int(arg: &str) -> Result<i32, String> {
+    arg.parse().map_err(|_| format!("'{}' is not a valid i32", arg))
+}
+
+pub fn parse_size(arg: &str) -> Result<SizeT, String> {
+    match arg.parse() {
+        Ok(size) if size >= 3 => Ok(size),
+        _ => Err(format!("'{}' is not a valid max size, it must be an integer >= 3", arg))
+    }
+}
@@ -0,0 +1,52 @@
+pub mod b_plus_tree_shell;
+
+
+#[cfg(test)]
+mod tests {
+    use crate::shell::b_plus_tree_shell::BPlusTreeShell;
+
+    fn run_script(script: &str) -> String {
+        let mut shell = BPlusTreeShell::new(3, 3);
+        let mut out = Vec::new();
+        shell.run(script.as_bytes(), &mut out, false).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn b_plus_tree_shell_test() {
+        let output = run_script("\
+# build a small tree
+insert 1 10
+insert 2 20
+insert 3 30
+insert 3 31
+get 3
+get 4
+range 1 3
+remove 2
+range 0 10
+validate
+");
+        assert_eq!("true\ntrue\ntrue\nfalse\n30\nNone\n10 20\n10 30\nok\n", output);
+
+        let output = run_script("new 4 5\ninsert 1 1\nstats\nprint\ndraw\n");
+        assert!(output.starts_with("true\nheight: 1\n"));
+        assert!(output.contains("Leaf Page:") && output.contains("max_size=5"));
+        assert!(output.ends_with("}\n"));
+
+        let output = run_script("print\nexplain 1\nquit\ninsert 1 1\n");
+        assert_eq!("Tree is Empty!\nget 1\nresult: None\n", output);
+    }
+
+    #[test]
+    fn b_plus_tree_shell_error_test() {
+        let output = run_script("insert x 1\nget\nfoo 1\nnew 2 3\ninsert 1 1\n");
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(5, lines.len());
+        assert_eq!("line 1: error: 'x' is not a valid i32", lines[0]);
+        assert!(lines[1].starts_with("line 2: error: unknown command"));
+        assert!(lines[2].starts_with("line 3: error: unknown command"));
+        assert!(lines[3].starts_with("line 4: error: '2' is not a valid max size"));
+        assert_eq!("true", lines[4]);
+    }
+}